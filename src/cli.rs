@@ -0,0 +1,108 @@
+use clap::{value_parser, Arg, ArgAction, Command};
+use std::path::PathBuf;
+
+pub fn get_app_cli(version: &'static str) -> Command {
+    Command::new("infra-test")
+        .version(version)
+        .about("Compares a Bicep what-if with a Terraform plan for the same infrastructure.")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .env("INFRA_TEST_CONFIG")
+                .default_value("tests/parameters.yaml")
+                .help("Path to the YAML config file."),
+        )
+        .arg(
+            Arg::new("suite")
+                .short('s')
+                .long("suite")
+                .env("INFRA_TEST_SUITES")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Name of a suite to run.  Can be repeated; runs every suite when omitted."),
+        )
+        .arg(
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .env("INFRA_TEST_PROFILE")
+                .help("Name of the environment profile to apply to the config."),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .env("INFRA_TEST_JOBS")
+                .value_parser(value_parser!(usize))
+                .default_value("1")
+                .help("Number of suite runs to execute at the same time."),
+        )
+        .arg(
+            Arg::new("skip-auth")
+                .long("skip-auth")
+                .action(ArgAction::SetTrue)
+                .help("Use the current Azure CLI login without checking it."),
+        )
+        .arg(
+            Arg::new("skip-init")
+                .long("skip-init")
+                .action(ArgAction::SetTrue)
+                .help("Skip `terraform init` for an already initialized directory."),
+        )
+        .arg(
+            Arg::new("bicep-only")
+                .long("bicep-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("terraform-only")
+                .help("Only run the Bicep what-if."),
+        )
+        .arg(
+            Arg::new("terraform-only")
+                .long("terraform-only")
+                .action(ArgAction::SetTrue)
+                .help("Only run the Terraform plan."),
+        )
+        .arg(
+            Arg::new("refresh-only")
+                .long("refresh-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("bicep-only")
+                .help("Only run `terraform plan -refresh-only` and report resources that drifted from the Terraform state."),
+        )
+        .arg(
+            Arg::new("compare-only")
+                .long("compare-only")
+                .action(ArgAction::SetTrue)
+                .help("Compare the outputs saved in the artifacts directory without running either tool."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Log every az and terraform command line and its output at info level."),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .env("INFRA_TEST_PROGRESS")
+                .action(ArgAction::SetTrue)
+                .help("Report Terraform refresh and plan progress, and what-if activity, while the tools run, logged at info level (raw what-if stderr at debug level)."),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .env("INFRA_TEST_TIMEOUT")
+                .value_parser(humantime::parse_duration)
+                .help("Stop any az or terraform command that runs longer than this, e.g. 30m.  Overrides commandTimeout in the config."),
+        )
+        .arg(
+            Arg::new("artifacts-dir")
+                .long("artifacts-dir")
+                .alias("cache-dir")
+                .env("INFRA_TEST_ARTIFACTS_DIR")
+                .value_parser(value_parser!(PathBuf))
+                .help("Directory to write raw tool output, logs, the effective config, the report and a run manifest to."),
+        )
+}
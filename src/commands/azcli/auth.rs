@@ -0,0 +1,265 @@
+use super::{get_az_cli_command, AzCliError};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, info};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{env, fmt, fs};
+
+/// How the Azure CLI (and through the `ARM_*` variables, Terraform) signs in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthenticationMethod {
+    /// Reuse the existing `az` login, prompting interactively when there is none.
+    #[default]
+    AzureCli,
+    ServicePrincipalSecret,
+    ServicePrincipalCertificate,
+    /// Workload identity federation, e.g. a GitHub Actions OIDC token written to a file.
+    FederatedToken,
+    ManagedIdentity,
+    DeviceCode,
+}
+
+/// A value that is never written to logs, reports or artifacts.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Credentials for non-interactive sign in.  Anything not set in the config
+/// is read from the `AZURE_*` variables, then from the matching `ARM_*` ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct AzureAuthentication {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<AuthenticationMethod>,
+    #[serde(rename = "clientId", default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(rename = "clientSecret", default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<Secret>,
+    #[serde(rename = "certificatePath", default, skip_serializing_if = "Option::is_none")]
+    pub certificate_path: Option<String>,
+    #[serde(rename = "certificatePassword", default, skip_serializing_if = "Option::is_none")]
+    pub certificate_password: Option<Secret>,
+    #[serde(rename = "federatedTokenFile", default, skip_serializing_if = "Option::is_none")]
+    pub federated_token_file: Option<String>,
+    #[serde(rename = "tenantId", default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+impl AzureAuthentication {
+    /// Fills the unset fields from the environment and, when no method is
+    /// configured, picks one from the credentials that are available.
+    pub fn resolve(&self) -> AzureAuthentication {
+        let mut auth = self.clone();
+        auth.client_id = auth
+            .client_id
+            .or_else(|| get_env(&["AZURE_CLIENT_ID", "ARM_CLIENT_ID"]));
+        auth.client_secret = auth
+            .client_secret
+            .or_else(|| get_env(&["AZURE_CLIENT_SECRET", "ARM_CLIENT_SECRET"]).map(Secret));
+        auth.certificate_path = auth.certificate_path.or_else(|| {
+            get_env(&["AZURE_CLIENT_CERTIFICATE_PATH", "ARM_CLIENT_CERTIFICATE_PATH"])
+        });
+        auth.certificate_password = auth.certificate_password.or_else(|| {
+            get_env(&["AZURE_CLIENT_CERTIFICATE_PASSWORD", "ARM_CLIENT_CERTIFICATE_PASSWORD"])
+                .map(Secret)
+        });
+        auth.federated_token_file = auth
+            .federated_token_file
+            .or_else(|| get_env(&["AZURE_FEDERATED_TOKEN_FILE", "ARM_OIDC_TOKEN_FILE_PATH"]));
+        auth.tenant_id = auth
+            .tenant_id
+            .or_else(|| get_env(&["AZURE_TENANT_ID", "ARM_TENANT_ID"]));
+
+        if auth.method.is_none() {
+            auth.method = Some(if auth.client_id.is_none() {
+                AuthenticationMethod::AzureCli
+            } else if auth.federated_token_file.is_some() {
+                AuthenticationMethod::FederatedToken
+            } else if auth.certificate_path.is_some() {
+                AuthenticationMethod::ServicePrincipalCertificate
+            } else if auth.client_secret.is_some() {
+                AuthenticationMethod::ServicePrincipalSecret
+            } else {
+                AuthenticationMethod::AzureCli
+            });
+        }
+        auth
+    }
+
+    pub fn get_method(&self) -> AuthenticationMethod {
+        self.method.clone().unwrap_or_default()
+    }
+
+    /// The azurerm provider settings that sign Terraform in the same way.
+    pub fn get_terraform_environment(&self) -> Vec<(String, String)> {
+        let mut variables: Vec<(&str, String)> = Vec::new();
+        match self.get_method() {
+            AuthenticationMethod::AzureCli | AuthenticationMethod::DeviceCode => {
+                variables.push(("ARM_USE_CLI", "true".to_string()));
+            }
+            AuthenticationMethod::ServicePrincipalSecret => {
+                if let Some(secret) = &self.client_secret {
+                    variables.push(("ARM_CLIENT_SECRET", secret.expose().to_string()));
+                }
+            }
+            AuthenticationMethod::ServicePrincipalCertificate => {
+                if let Some(path) = &self.certificate_path {
+                    variables.push(("ARM_CLIENT_CERTIFICATE_PATH", path.clone()));
+                }
+                if let Some(password) = &self.certificate_password {
+                    variables.push(("ARM_CLIENT_CERTIFICATE_PASSWORD", password.expose().to_string()));
+                }
+            }
+            AuthenticationMethod::FederatedToken => {
+                variables.push(("ARM_USE_OIDC", "true".to_string()));
+                if let Some(path) = &self.federated_token_file {
+                    variables.push(("ARM_OIDC_TOKEN_FILE_PATH", path.clone()));
+                }
+            }
+            AuthenticationMethod::ManagedIdentity => {
+                variables.push(("ARM_USE_MSI", "true".to_string()));
+            }
+        }
+        if self.get_method() != AuthenticationMethod::AzureCli {
+            if let Some(client_id) = &self.client_id {
+                variables.push(("ARM_CLIENT_ID", client_id.clone()));
+            }
+        }
+        if let Some(tenant_id) = &self.tenant_id {
+            variables.push(("ARM_TENANT_ID", tenant_id.clone()));
+        }
+        variables
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+}
+
+/// Signs the Azure CLI in without prompting, using a service principal,
+/// federated token or managed identity.
+pub(super) fn login_non_interactive(auth: &AzureAuthentication) -> Result<()> {
+    let method = auth.get_method();
+    info!("Logging in to the Azure CLI with {:?}.", method);
+    let client_id = auth.client_id.as_deref();
+    let tenant_id = auth.tenant_id.as_deref();
+
+    let federated_token;
+    let mut args: Vec<&str> = Vec::new();
+    match method {
+        AuthenticationMethod::ServicePrincipalSecret => {
+            let secret = auth.client_secret.as_ref().ok_or_else(|| missing("clientSecret"))?;
+            args.extend(["--service-principal", "--username", client_id.ok_or_else(|| missing("clientId"))?]);
+            args.extend(["--password", secret.expose()]);
+            args.extend(["--tenant", tenant_id.ok_or_else(|| missing("tenantId"))?]);
+        }
+        AuthenticationMethod::ServicePrincipalCertificate => {
+            let path = auth.certificate_path.as_deref().ok_or_else(|| missing("certificatePath"))?;
+            args.extend(["--service-principal", "--username", client_id.ok_or_else(|| missing("clientId"))?]);
+            args.extend(["--certificate", path]);
+            args.extend(["--tenant", tenant_id.ok_or_else(|| missing("tenantId"))?]);
+        }
+        AuthenticationMethod::FederatedToken => {
+            let path = auth
+                .federated_token_file
+                .as_deref()
+                .ok_or_else(|| missing("federatedTokenFile"))?;
+            federated_token = fs::read_to_string(path)?;
+            args.extend(["--service-principal", "--username", client_id.ok_or_else(|| missing("clientId"))?]);
+            args.extend(["--federated-token", federated_token.trim()]);
+            args.extend(["--tenant", tenant_id.ok_or_else(|| missing("tenantId"))?]);
+        }
+        AuthenticationMethod::ManagedIdentity => {
+            args.push("--identity");
+            if let Some(client_id) = client_id {
+                args.extend(["--username", client_id]);
+            }
+        }
+        AuthenticationMethod::AzureCli | AuthenticationMethod::DeviceCode => {
+            return Err(anyhow!("{:?} sign in is interactive.", method));
+        }
+    }
+
+    let command = get_az_cli_command("login")?
+        .with_args(args)
+        .with_redacted_args(true)
+        .run()?;
+    if command.success() {
+        debug!("Azure CLI login succeeded.");
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Azure CLI login failed: {}",
+            command.get_stderr().unwrap_or_default().trim()
+        ))
+    }
+}
+
+fn missing(setting: &str) -> Error {
+    Error::new(AzCliError::NoParameters).context(format!(
+        "The configured authentication method needs `{}`.",
+        setting
+    ))
+}
+
+fn get_env(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+#[cfg(test)]
+mod auth_test {
+    use super::*;
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let yaml = r#"
+        method: servicePrincipalSecret
+        clientId: 00000000-0000-0000-0000-000000000001
+        clientSecret: hunter2
+        tenantId: 00000000-0000-0000-0000-000000000002
+        "#;
+        let auth: AzureAuthentication = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(auth.client_secret.as_ref().unwrap().expose(), "hunter2");
+        assert!(!format!("{:?}", auth).contains("hunter2"));
+        assert!(!serde_yaml::to_string(&auth).unwrap().contains("hunter2"));
+    }
+
+    #[test]
+    fn test_terraform_environment_for_federated_token() {
+        let auth = AzureAuthentication {
+            method: Some(AuthenticationMethod::FederatedToken),
+            client_id: Some("client".to_string()),
+            federated_token_file: Some("/tmp/token".to_string()),
+            tenant_id: Some("tenant".to_string()),
+            ..Default::default()
+        };
+        let variables = auth.get_terraform_environment();
+        assert!(variables.contains(&("ARM_USE_OIDC".to_string(), "true".to_string())));
+        assert!(variables.contains(&("ARM_OIDC_TOKEN_FILE_PATH".to_string(), "/tmp/token".to_string())));
+        assert!(variables.contains(&("ARM_CLIENT_ID".to_string(), "client".to_string())));
+        assert!(variables.contains(&("ARM_TENANT_ID".to_string(), "tenant".to_string())));
+    }
+}
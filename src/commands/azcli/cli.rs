@@ -1,11 +1,18 @@
 #![allow(dead_code)]
-use super::get_az_cli_command;
+use super::{
+    get_az_cli_command, login_non_interactive, set_azure_cloud, AuthenticationMethod,
+    AzureAuthentication, AzureCloud,
+};
 use anyhow::{Error, Result};
 use custom_error::custom_error;
-use log::{info, trace};
+use log::{info, trace, warn};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::io::{BufRead, BufReader};
+use std::{
+    env,
+    io::{BufRead, BufReader},
+};
 
 custom_error! {
     pub AzCliError
@@ -21,33 +28,63 @@ custom_error! {
     TemplateFailed = "Deployment did not achieve the desired result.",
 }
 
-#[derive(Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
 pub struct AzAccountInfo {
-    subscription_name: Option<String>,
-    subscription_id: Option<String>,
-    tenant_id: Option<String>,
+    pub subscription_name: Option<String>,
+    pub subscription_id: Option<String>,
+    pub tenant_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_name: Option<String>,
 }
 
-pub fn set_azure_environment(subscription: Option<&str>) -> Result<()> {
+pub fn set_azure_environment(
+    subscription: Option<&str>,
+    authentication: &AzureAuthentication,
+    cloud: AzureCloud,
+) -> Result<AzAccountInfo> {
     trace!("Entering set azure environment.");
-    info!(
-        "Checking to see if the Azure CLI is authenticated and which subscription is default."
-    );
-    let account = match get_account_info() {
-        Ok(a) => a,
-        Err(_) => {
-            trace!("Failed to get existing login information.  Prompting for new login.");
-            login()?;
-            info!("Checking for the default subscription.");
+    set_azure_cloud(cloud)?;
+    let method = authentication.get_method();
+    let account = match method {
+        AuthenticationMethod::AzureCli | AuthenticationMethod::DeviceCode => {
+            info!(
+                "Checking to see if the Azure CLI is authenticated and which subscription is default."
+            );
+            match get_account_info() {
+                Ok(a) => a,
+                Err(_) if method == AuthenticationMethod::AzureCli && env::var_os("CI").is_some() => {
+                    return Err(Error::new(AzCliError::NotLoggedIn).context(
+                        "Not prompting for an interactive login in CI.  Configure a non-interactive authentication method.",
+                    ));
+                }
+                Err(_) => {
+                    trace!("Failed to get existing login information.  Prompting for new login.");
+                    if method == AuthenticationMethod::DeviceCode {
+                        login(vec!["--use-device-code"], authentication.tenant_id.as_deref())?;
+                    } else {
+                        login(Vec::new(), authentication.tenant_id.as_deref())?;
+                    }
+                    info!("Checking for the default subscription.");
+                    get_account_info()?
+                }
+            }
+        }
+        _ => {
+            login_non_interactive(authentication)?;
             get_account_info()?
         }
     };
 
-    if let Some(account_subscription) = account.subscription_name {
-        info!("The default subscription is {}", &account_subscription);
+    if let Some(account_subscription) = &account.subscription_name {
+        info!("The default subscription is {}", account_subscription);
 
         if let Some(target_subscription) = subscription {
-            if account_subscription.trim_matches('"') == target_subscription {
+            if account_subscription == target_subscription
+                || account
+                    .subscription_id
+                    .as_deref()
+                    .is_some_and(|id| id.eq_ignore_ascii_case(target_subscription))
+            {
                 info!("Subscription already configured correctly.\n");
             } else {
                 info!(
@@ -55,15 +92,40 @@ pub fn set_azure_environment(subscription: Option<&str>) -> Result<()> {
                     &target_subscription
                 );
                 set_target_subscription(target_subscription)?;
+                return get_account_info();
             }
         }
     }
 
-    Ok(())
+    Ok(account)
+}
+
+pub fn get_az_cli_version() -> Result<String> {
+    let command = get_az_cli_command("version")?
+        .with_args(vec!["--output", "json"])
+        .run()?;
+    let v: Value = serde_json::from_str(&command.get_stdout().unwrap_or_default())?;
+    match v["azure-cli"].as_str() {
+        Some(version) => Ok(version.to_string()),
+        None => Err(Error::new(AzCliError::Unknown)),
+    }
+}
+
+/// The version of the Bicep CLI the Azure CLI builds templates with.
+pub fn get_bicep_version() -> Result<String> {
+    let command = get_az_cli_command("bicep")?
+        .with_args(vec!["version"])
+        .run()?;
+    let re = Regex::new(r"Bicep CLI version (\d+\.\d+\.\d+)")?;
+    let stdout = command.get_stdout().unwrap_or_default();
+    match re.captures(&stdout) {
+        Some(captures) => Ok(captures[1].to_string()),
+        None => Err(Error::new(AzCliError::Unknown).context("The Bicep CLI did not report a version.")),
+    }
 }
 
 fn get_account_info() -> Result<AzAccountInfo> {
-    let command = get_az_cli_command("account")
+    let command = get_az_cli_command("account")?
         .with_args(vec!["show", "--output", "json"])
         .run()?;
 
@@ -80,9 +142,10 @@ fn get_account_info() -> Result<AzAccountInfo> {
         let v: Value = serde_json::from_str(stdout)?;
 
         let current_account = AzAccountInfo {
-            subscription_id: Some(v["id"].to_string()),
-            subscription_name: Some(v["name"].to_string()),
-            tenant_id: Some(v["tenantId"].to_string()),
+            subscription_id: v["id"].as_str().map(String::from),
+            subscription_name: v["name"].as_str().map(String::from),
+            tenant_id: v["tenantId"].as_str().map(String::from),
+            cloud_name: v["environmentName"].as_str().map(String::from),
         };
 
         return_value = Ok(current_account);
@@ -91,11 +154,15 @@ fn get_account_info() -> Result<AzAccountInfo> {
     return_value
 }
 
-fn login() -> Result<()> {
-    let error_pipe_reader = get_az_cli_command("login")
+fn login<'a>(mut args: Vec<&'a str>, tenant_id: Option<&'a str>) -> Result<()> {
+    if let Some(tenant_id) = tenant_id {
+        args.extend(["--tenant", tenant_id]);
+    }
+    let error_pipe_reader = get_az_cli_command("login")?
+        .with_args(args)
         .stderr_reader()?;
 
-    for line in BufReader::new(error_pipe_reader).lines().flatten() {
+    for line in BufReader::new(error_pipe_reader).lines().map_while(Result::ok) {
         let logged_in_regex = r"^WARNING: (You have logged in\.)";
         let warning_regex = r"^WARNING: (.*)$";
         let warn = Regex::new(warning_regex).expect("Boom");
@@ -104,6 +171,9 @@ fn login() -> Result<()> {
         if let Some(m) = warn.captures(&line) {
             if let Some(m2) = logged_in.captures(&line) {
                 info!("{}", &m2[1]);
+            } else if m[1].contains("devicelogin") {
+                // The device code prompt has to reach the user at any log level.
+                warn!("{}", &m[1]);
             } else {
                 info!("{}", &m[1]);
             }
@@ -113,7 +183,7 @@ fn login() -> Result<()> {
 }
 
 fn set_target_subscription(subscription_name: &str) -> Result<()> {
-    let command = get_az_cli_command("account")
+    let command = get_az_cli_command("account")?
         .with_args(vec![ "set", "--subscription", subscription_name])
         .run()?;
 
@@ -0,0 +1,61 @@
+use super::{get_az_cli_command, AzCliError};
+use anyhow::{Error, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+/// The Azure cloud both tools target.  Serialized with the names `az cloud
+/// list` uses.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AzureCloud {
+    #[default]
+    #[serde(rename = "AzureCloud", alias = "public")]
+    Public,
+    #[serde(rename = "AzureUSGovernment", alias = "usGovernment")]
+    UsGovernment,
+    #[serde(rename = "AzureChinaCloud", alias = "china")]
+    China,
+}
+
+impl AzureCloud {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            AzureCloud::Public => "AzureCloud",
+            AzureCloud::UsGovernment => "AzureUSGovernment",
+            AzureCloud::China => "AzureChinaCloud",
+        }
+    }
+
+    /// The value the azurerm and azuread providers expect in `ARM_ENVIRONMENT`.
+    pub fn get_terraform_environment(&self) -> &'static str {
+        match self {
+            AzureCloud::Public => "public",
+            AzureCloud::UsGovernment => "usgovernment",
+            AzureCloud::China => "china",
+        }
+    }
+}
+
+/// Switches the Azure CLI to `cloud` unless it is already active.  Switching
+/// clouds signs the CLI out, so this has to happen before any login.
+pub fn set_azure_cloud(cloud: AzureCloud) -> Result<()> {
+    let command = get_az_cli_command("cloud")?
+        .with_args(vec!["show", "--query", "name", "--output", "tsv"])
+        .run()?;
+    let active_cloud = command.get_stdout().unwrap_or_default();
+    if active_cloud.trim().eq_ignore_ascii_case(cloud.get_name()) {
+        return Ok(());
+    }
+
+    info!("Setting the Azure CLI cloud to {}", cloud.get_name());
+    let command = get_az_cli_command("cloud")?
+        .with_args(vec!["set", "--name", cloud.get_name()])
+        .run()?;
+    if command.success() {
+        Ok(())
+    } else {
+        Err(Error::new(AzCliError::Unknown).context(format!(
+            "Unable to set the Azure CLI cloud to {}",
+            cloud.get_name()
+        )))
+    }
+}
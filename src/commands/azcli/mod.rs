@@ -1,42 +1,66 @@
 #![allow(unused_assignments)]
 
+mod auth;
 mod cli;
+mod cloud;
 
 use super::{Command, find_command};
-use anyhow::Result;
+use anyhow::{Error, Result};
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::RwLock};
 
 
+pub use auth::*;
 pub use cli::*;
+pub use cloud::*;
 
 
+static AZ_CLI_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
 
-lazy_static! {
-    pub static ref AZ_CLI_PATH: PathBuf = get_az_cli_path().unwrap();
+/// Uses `binary`, a path or a name to look up on `PATH`, for every Azure CLI
+/// command instead of the `az` found on `PATH`.
+pub fn set_az_cli_binary(binary: &str) -> Result<()> {
+    let cli_path = find_command(binary).ok_or_else(|| {
+        Error::new(AzCliError::CliMissing).context(format!("Unable to find {}.", binary))
+    })?;
+    *AZ_CLI_PATH.write().unwrap() = Some(cli_path);
+    Ok(())
 }
 
-pub fn get_az_cli_command(subcommand: &str) -> Command<'static> {
-    Command {
+pub fn get_az_cli_command(subcommand: &str) -> Result<Command<'static>> {
+    Ok(Command {
         name: "az".to_owned(),
         subcommand: subcommand.to_owned(),
-        path: AZ_CLI_PATH.clone(),
+        path: get_az_cli_path()?,
         args: Vec::new(),
         stdout: None,
         stderr: None,
         exit_status: None,
         verbose: false,
         show_progress: false,
+        redact_args: false,
         working_directory: None,
-    }
+        env: Vec::new(),
+        stdin: None,
+        timeout: None,
+        progress_label: None,
+        stdout_handler: None,
+        stderr_handler: None,
+    })
 }
 
 fn get_az_cli_path() -> Result<PathBuf> {
+    if let Some(cli_path) = AZ_CLI_PATH.read().unwrap().as_ref() {
+        return Ok(cli_path.clone());
+    }
     let cmd_name = if cfg!(target_os = "windows") {
         "az.cmd"
     } else {
         "az"
     };
-    let cli_path = find_command(cmd_name).expect("Failed to find the Az CLI.  Please install the Az CLI to continue (https://aka.ms/containerapps/install-az-cli) or use --skip-azure to only process the Compose files.");
+    let cli_path = find_command(cmd_name).ok_or_else(|| {
+        Error::new(AzCliError::CliMissing).context("Please install the Az CLI to continue (https://aka.ms/containerapps/install-az-cli) or set azCliBinary in the config.")
+    })?;
+    *AZ_CLI_PATH.write().unwrap() = Some(cli_path.clone());
     Ok(cli_path)
 }
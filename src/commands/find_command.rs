@@ -9,6 +9,14 @@ where
     if command.as_ref().is_absolute() && command.as_ref().is_file() {
         return Some(command.as_ref().to_path_buf());
     }
+    // A relative path to a specific binary, like `./bin/terraform`, is used
+    // as is rather than looked up on `PATH`.
+    if command.as_ref().components().count() > 1 {
+        return command
+            .as_ref()
+            .is_file()
+            .then(|| command.as_ref().to_path_buf());
+    }
     // Find the command by checking each entry in `PATH`. If we still can't find it, give up and
     // return `None`.
     match env::var_os("PATH") {
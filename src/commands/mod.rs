@@ -1,15 +1,28 @@
 mod azcli;
 mod find_command;
+mod process_tree;
 mod terraform;
 
 pub use azcli::*;
-use duct::{cmd, ReaderHandle};
-use anyhow::Result;
-use log::{trace, debug};
-use std::{path::PathBuf, process::ExitStatus};
+use duct::{cmd, Expression, ReaderHandle};
+use anyhow::{anyhow, Result};
+use log::{info, trace, log, Level};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command as ProcessCommand, ExitStatus, Stdio},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 pub use find_command::*;
+pub use process_tree::*;
 pub use terraform::*;
 
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Clone, Debug)]
 pub struct Command<'a> {
     name: String,
@@ -21,7 +34,34 @@ pub struct Command<'a> {
     exit_status: Option<ExitStatus>,
     verbose: bool,
     show_progress: bool,
+    redact_args: bool,
     working_directory: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    progress_label: Option<String>,
+    stdout_handler: Option<LineHandler>,
+    stderr_handler: Option<LineHandler>,
+}
+
+/// Called with each line of output as the child writes it, without the line
+/// ending.
+#[derive(Clone)]
+pub struct LineHandler(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl LineHandler {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        LineHandler(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for LineHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LineHandler")
+    }
 }
 
 impl<'a> Command<'a> {
@@ -43,32 +83,92 @@ impl<'a> Command<'a> {
         self
     }
 
-    #[allow(dead_code)]
     pub fn with_verbose(mut self, verbose: bool) -> Self {
         self.verbose = verbose;
         self
     }
 
-    pub fn with_working_directory(mut self, working_directory: &PathBuf) -> Self {
-        self.working_directory = Some(working_directory.clone());
+    pub fn with_working_directory(mut self, working_directory: &Path) -> Self {
+        self.working_directory = Some(working_directory.to_path_buf());
         self
     }
 
-    #[allow(dead_code)]
-    pub fn with_show_progress(mut self, show_progress: bool) -> Self {
+    /// Reports every 30 seconds that the command is still running, labelled
+    /// with `label` so concurrent runs can be told apart.
+    pub fn with_show_progress(mut self, show_progress: bool, label: &str) -> Self {
         self.show_progress = show_progress;
+        self.progress_label = Some(label.to_owned());
+        self
+    }
+
+    /// Streams stdout to `handler` while the command runs.  The output is
+    /// still collected for `get_stdout`.
+    pub fn with_stdout_handler(mut self, handler: LineHandler) -> Self {
+        self.stdout_handler = Some(handler);
+        self
+    }
+
+    /// Streams stderr to `handler` while the command runs.  The output is
+    /// still collected for `get_stderr`.
+    pub fn with_stderr_handler(mut self, handler: LineHandler) -> Self {
+        self.stderr_handler = Some(handler);
+        self
+    }
+
+    /// Sets environment variables for this invocation only, on top of the
+    /// inherited environment.
+    pub fn with_env<I>(mut self, env: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.env.extend(env);
+        self
+    }
+
+    /// Input written to the child's stdin.  Without it `run` gives the child
+    /// a null stdin, so a tool that prompts fails instead of waiting forever.
+    #[allow(dead_code)]
+    pub fn with_stdin(mut self, input: &[u8]) -> Self {
+        self.stdin = Some(input.to_vec());
         self
     }
 
+    /// Kills the child, and everything it started, once `timeout` passes.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keeps the arguments out of the logs, for commands that take secrets.
+    pub fn with_redacted_args(mut self, redact_args: bool) -> Self {
+        self.redact_args = redact_args;
+        self
+    }
+
+    fn get_logged_args(&self) -> String {
+        if self.redact_args {
+            "<redacted>".to_string()
+        } else {
+            self.args.join(" ")
+        }
+    }
+
     pub fn get_stdout(&self) -> Option<String> {
         self.stdout.clone()
     }
 
-    #[allow(dead_code)]
     pub fn get_stderr(&self) -> Option<String> {
         self.stderr.clone()
     }
 
+    pub fn get_display_name(&self) -> String {
+        format!("{} {}", &self.name, &self.subcommand)
+    }
+
+    pub fn get_exit_code(&self) -> Option<i32> {
+        self.exit_status.and_then(|s| s.code())
+    }
+
     pub fn success(&self) -> bool {
         if let Some(s) = &self.exit_status {
             s.success()
@@ -77,56 +177,224 @@ impl<'a> Command<'a> {
         }
     }
 
-    pub fn stdout_reader(&self) -> Result<ReaderHandle> {
-        trace!("Command: {} {} running", &self.name, &self.subcommand);
-        debug!("\t`{} {} {}`", &self.name, &self.subcommand, &self.args.join(" "));
+    fn get_expression(&self) -> Expression {
         let mut command_args: Vec<&str> = Vec::new();
         command_args.push(&self.subcommand);
         for arg in &self.args {
             command_args.push(arg);
         }
-        let reader = cmd(&self.path, command_args).stderr_capture().reader()?;
+        let mut command = cmd(&self.path, command_args);
+        if let Some(working_directory) = &self.working_directory {
+            command = command.dir(working_directory);
+        }
+        for (name, value) in &self.env {
+            command = command.env(name, value);
+        }
+        command
+    }
+
+    fn get_log_level(&self) -> Level {
+        if self.verbose {
+            Level::Info
+        } else {
+            Level::Debug
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn stdout_reader(&self) -> Result<ReaderHandle> {
+        trace!("Command: {} {} running", &self.name, &self.subcommand);
+        log!(self.get_log_level(), "\t`{} {} {}`", &self.name, &self.subcommand, &self.get_logged_args());
+        let reader = self.get_expression().stderr_capture().reader()?;
         trace!("Returning reader handle.");
         Ok(reader)
     }
 
     pub fn stderr_reader(&self) -> Result<ReaderHandle> {
         trace!("Command: {} {} running", &self.name, &self.subcommand);
-        debug!("\t`{} {} {}`", &self.name, &self.subcommand, &self.args.join(" "));
-        let mut command_args: Vec<&str> = Vec::new();
-        command_args.push(&self.subcommand);
-        for arg in &self.args {
-            command_args.push(arg);
-        }
-        let reader = cmd(&self.path, command_args).stdout_capture().reader()?;
+        log!(self.get_log_level(), "\t`{} {} {}`", &self.name, &self.subcommand, &self.get_logged_args());
+        let reader = self.get_expression().stdout_null().stderr_to_stdout().reader()?;
         trace!("Returning reader handle.");
         Ok(reader)
     }
 
     pub fn run(mut self) -> Result<Self> {
         trace!("Command: {} {} running", &self.name, &self.subcommand);
-        debug!("\t`{} {} {}`", &self.name, &self.subcommand, &self.args.join(" "));
-        let mut command_args: Vec<&str> = Vec::new();
-        command_args.push(&self.subcommand);
-        for arg in &self.args {
-            command_args.push(*arg);
+        log!(self.get_log_level(), "\t`{} {} {}`", &self.name, &self.subcommand, &self.get_logged_args());
+        if is_cancelled() {
+            return Err(anyhow!("{} was cancelled before it started.", self.get_display_name()));
         }
-        let mut command = cmd(&self.path, command_args);
+        let mut command = ProcessCommand::new(&self.path);
+        command
+            .arg(&self.subcommand)
+            .args(&self.args)
+            .envs(self.env.iter().map(|(name, value)| (name, value)))
+            .stdin(if self.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
         if let Some(working_directory) = &self.working_directory {
-            command = command.dir(working_directory);
+            command.current_dir(working_directory);
+        }
+        in_own_process_group(&mut command);
+        let mut child = command.spawn()?;
+        let _running = register_process(&child);
+
+        if let (Some(input), Some(mut pipe)) = (self.stdin.clone(), child.stdin.take()) {
+            thread::spawn(move || pipe.write_all(&input));
         }
-        let output = command
-            .stderr_capture()
-            .stdout_capture()
-            .unchecked()
-            .run()?;
-        self.stdout = Some(String::from_utf8(output.stdout)?);
-        self.stderr = Some(String::from_utf8(output.stderr)?);
-        self.exit_status = Some(output.status);
-        debug!("  Command stdout: {:?}", &self.stdout);
-        debug!("  Command stderr: {:?}", &self.stderr);
+        let stdout = spawn_reader(child.stdout.take(), self.stdout_handler.clone());
+        let stderr = spawn_reader(child.stderr.take(), self.stderr_handler.clone());
+        let status = self.wait_for(&mut child, &[&stdout, &stderr])?;
+
+        self.stdout = Some(String::from_utf8(join_reader(stdout)?)?);
+        self.stderr = Some(String::from_utf8(join_reader(stderr)?)?);
+        self.exit_status = Some(status);
+        log!(self.get_log_level(), "  Command stdout: {:?}", &self.stdout);
+        log!(self.get_log_level(), "  Command stderr: {:?}", &self.stderr);
         trace!("Finished with command {} {}", &self.name, &self.subcommand);
 
         Ok(self)
     }
-}
\ No newline at end of file
+
+    /// Waits for the child to exit and its output to be drained, stopping
+    /// its process tree on timeout or cancellation.
+    fn wait_for(&self, child: &mut Child, readers: &[&Reader]) -> Result<ExitStatus> {
+        let started_at = Instant::now();
+        let mut reported_at = started_at;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if readers.iter().all(|x| x.is_finished()) {
+                    return Ok(status);
+                }
+            }
+            if is_cancelled() {
+                stop_process_tree(child);
+                return Err(anyhow!("{} was cancelled.", self.get_display_name()));
+            }
+            if let Some(timeout) = self.timeout.filter(|x| started_at.elapsed() >= *x) {
+                stop_process_tree(child);
+                return Err(anyhow!(
+                    "{} timed out after {}.",
+                    self.get_display_name(),
+                    humantime::format_duration(timeout)
+                ));
+            }
+            if self.show_progress && reported_at.elapsed() >= PROGRESS_INTERVAL {
+                reported_at = Instant::now();
+                info!(
+                    "{} {} still running after {}.",
+                    self.progress_label.as_deref().unwrap_or_default(),
+                    self.get_display_name(),
+                    humantime::format_duration(Duration::from_secs(started_at.elapsed().as_secs()))
+                );
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+type Reader = JoinHandle<io::Result<Vec<u8>>>;
+
+fn spawn_reader<R: Read + Send + 'static>(pipe: Option<R>, handler: Option<LineHandler>) -> Reader {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        match (pipe, handler) {
+            (Some(pipe), Some(LineHandler(handler))) => {
+                let mut reader = BufReader::new(pipe);
+                loop {
+                    let start = buffer.len();
+                    if reader.read_until(b'\n', &mut buffer)? == 0 {
+                        break;
+                    }
+                    let line = String::from_utf8_lossy(&buffer[start..]);
+                    handler(line.trim_end_matches(['\r', '\n']));
+                }
+            }
+            (Some(mut pipe), None) => {
+                pipe.read_to_end(&mut buffer)?;
+            }
+            (None, _) => {}
+        }
+        Ok(buffer)
+    })
+}
+
+fn join_reader(reader: Reader) -> Result<Vec<u8>> {
+    reader
+        .join()
+        .map_err(|_| anyhow!("The output reader panicked."))?
+        .map_err(|e| e.into())
+}
+
+#[cfg(all(test, unix))]
+mod command_test {
+    use super::*;
+
+    fn get_shell_command(script: &str) -> Command<'_> {
+        Command {
+            name: "sh".to_owned(),
+            subcommand: "-c".to_owned(),
+            path: PathBuf::from("/bin/sh"),
+            args: vec![script],
+            stdout: None,
+            stderr: None,
+            exit_status: None,
+            verbose: false,
+            show_progress: false,
+            redact_args: false,
+            working_directory: None,
+            env: Vec::new(),
+            stdin: None,
+            timeout: None,
+            progress_label: None,
+            stdout_handler: None,
+            stderr_handler: None,
+        }
+    }
+
+    #[test]
+    fn test_env_and_stdin() {
+        let command = get_shell_command("echo $INFRA_TEST_VALUE; cat")
+            .with_env([("INFRA_TEST_VALUE".to_string(), "from env".to_string())])
+            .with_stdin(b"from stdin")
+            .run()
+            .unwrap();
+        assert!(command.success());
+        assert_eq!(command.get_stdout().unwrap(), "from env\nfrom stdin");
+    }
+
+    #[test]
+    fn test_stdout_handler_sees_each_line() {
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = lines.clone();
+        let command = get_shell_command("printf 'one\\r\\ntwo\\nthree'")
+            .with_stdout_handler(LineHandler::new(move |line| {
+                seen.lock().unwrap().push(line.to_string())
+            }))
+            .run()
+            .unwrap();
+        assert_eq!(*lines.lock().unwrap(), vec!["one", "two", "three"]);
+        assert_eq!(command.get_stdout().unwrap(), "one\r\ntwo\nthree");
+    }
+
+    #[test]
+    fn test_stderr_reader_reads_stderr() {
+        let mut output = String::new();
+        get_shell_command("echo out; echo err >&2")
+            .stderr_reader()
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "err\n");
+    }
+
+    #[test]
+    fn test_timeout_stops_process_tree() {
+        let started_at = Instant::now();
+        let result = get_shell_command("sleep 30; sleep 30")
+            .with_timeout(Some(Duration::from_millis(200)))
+            .run();
+        assert!(result.is_err());
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
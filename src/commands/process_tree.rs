@@ -0,0 +1,151 @@
+use log::{debug, warn};
+use std::{
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+/// The process ids of the running children, each the leader of its group.
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Stops every running command and keeps new ones from starting.  Returns
+/// false when cancellation was already requested.
+pub fn cancel_commands() -> bool {
+    !CANCELLED.swap(true, Ordering::SeqCst)
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Kills every running command's process tree without a grace period, for
+/// when the user will not wait for `cancel_commands` to stop them.  Called
+/// before exiting, so no child outlives us holding a Terraform state lock.
+pub fn kill_commands() {
+    let running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    for pid in running.iter() {
+        kill_process_tree(*pid);
+    }
+}
+
+/// Keeps a running child registered for `kill_commands` until dropped,
+/// which must happen only once the child has been reaped.
+pub(super) struct RunningProcess(u32);
+
+pub(super) fn register_process(child: &Child) -> RunningProcess {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner()).push(child.id());
+    RunningProcess(child.id())
+}
+
+impl Drop for RunningProcess {
+    fn drop(&mut self) {
+        RUNNING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|x| *x != self.0);
+    }
+}
+
+/// Starts the child as the leader of a new process group, so the tree can be
+/// stopped as a whole.  The terminal's Ctrl-C no longer reaches it directly;
+/// `cancel_commands` takes care of that.
+#[cfg(unix)]
+pub(super) fn in_own_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+pub(super) fn in_own_process_group(_command: &mut Command) {}
+
+/// Interrupts the child's process group, then kills whatever is left of it
+/// after the grace period.  Terraform uses the interrupt to release the
+/// state lock.
+#[cfg(unix)]
+pub(super) fn stop_process_tree(child: &mut Child) {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+    const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+    // A negative pid addresses the whole process group.
+    let group = -(child.id() as libc::pid_t);
+    debug!("Stopping process group {}", -group);
+    unsafe {
+        libc::kill(group, libc::SIGINT);
+    }
+    let deadline = Instant::now() + GRACE_PERIOD;
+    while Instant::now() < deadline {
+        // Reaping the leader lets the group disappear once the rest exit.
+        let _ = child.try_wait();
+        if unsafe { libc::kill(group, 0) } != 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    unsafe {
+        libc::kill(group, libc::SIGKILL);
+    }
+    if let Err(e) = child.wait() {
+        warn!("Failed to reap stopped process {}: {}", -group, e);
+    }
+}
+
+#[cfg(unix)]
+fn kill_process_tree(pid: u32) {
+    debug!("Killing process group {}", pid);
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(windows)]
+fn kill_process_tree(pid: u32) {
+    debug!("Killing process tree {}", pid);
+    if let Err(e) = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .output()
+    {
+        warn!("Failed to kill process tree {}: {}", pid, e);
+    }
+}
+
+#[cfg(windows)]
+pub(super) fn stop_process_tree(child: &mut Child) {
+    let pid = child.id().to_string();
+    debug!("Stopping process tree {}", pid);
+    if let Err(e) = Command::new("taskkill")
+        .args(["/PID", &pid, "/T", "/F"])
+        .output()
+    {
+        warn!("Failed to kill process tree {}: {}", pid, e);
+    }
+    if let Err(e) = child.wait() {
+        warn!("Failed to reap stopped process {}: {}", pid, e);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod process_tree_test {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_kill_process_tree() {
+        let mut command = Command::new("sleep");
+        command.arg("30");
+        in_own_process_group(&mut command);
+        let mut child = command.spawn().unwrap();
+        let running = register_process(&child);
+        assert!(RUNNING.lock().unwrap().contains(&child.id()));
+
+        kill_process_tree(child.id());
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+        drop(running);
+        assert!(!RUNNING.lock().unwrap().contains(&child.id()));
+    }
+}
@@ -1,33 +1,83 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::path::PathBuf;
 use super::{Command, find_command};
 
 
-lazy_static! {
-    pub static ref TERRAFORM_PATH: PathBuf = get_terraform_path().unwrap();
+/// A Terraform compatible CLI: `terraform` itself, a pinned Terraform binary
+/// or OpenTofu's `tofu`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TerraformCli {
+    name: String,
+    path: PathBuf,
 }
 
-pub fn get_terraform_command(subcommand: &str) -> Command<'static> {
-    Command {
-        name: "terraform".to_owned(),
-        subcommand: subcommand.to_owned(),
-        path: TERRAFORM_PATH.clone(),
-        args: Vec::new(),
-        stdout: None,
-        stderr: None,
-        exit_status: None,
-        verbose: false,
-        show_progress: false,
-        working_directory: None,
+impl TerraformCli {
+    /// Finds `binary`, a path or a name to look up on `PATH`, or `terraform`
+    /// when no binary is configured.
+    pub fn find(binary: Option<&str>) -> Result<Self> {
+        let binary = binary.unwrap_or(if cfg!(target_os = "windows") {
+            "terraform.exe"
+        } else {
+            "terraform"
+        });
+        let path = find_command(binary).ok_or_else(|| {
+            anyhow!("Failed to find {}.  Please install Terraform (https://www.terraform.io/downloads.html) or OpenTofu (https://opentofu.org/docs/intro/install/) to continue, or set terraformBinary in the config.", binary)
+        })?;
+        let stem = path
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let name = if stem.starts_with("tofu") {
+            "tofu"
+        } else {
+            "terraform"
+        };
+        Ok(TerraformCli {
+            name: name.to_string(),
+            path,
+        })
+    }
+
+    /// `terraform` or `tofu`, which is also the key its version is recorded under.
+    pub fn get_name(&self) -> &str {
+        &self.name
     }
-}
 
-fn get_terraform_path() -> Result<PathBuf> {
-    let cmd_name = if cfg!(target_os = "windows") {
-        "terraform.exe"
-    } else {
-        "terraform"
-    };
-    let cli_path = find_command(cmd_name).expect("Failed to find Terraform.  Please install the Terraform to continue.  https://www.terraform.io/downloads.html");
-    Ok(cli_path)
+    pub fn get_command(&self, subcommand: &str) -> Command<'static> {
+        Command {
+            name: self.name.clone(),
+            subcommand: subcommand.to_owned(),
+            path: self.path.clone(),
+            args: Vec::new(),
+            stdout: None,
+            stderr: None,
+            exit_status: None,
+            verbose: false,
+            show_progress: false,
+            redact_args: false,
+            working_directory: None,
+            env: Vec::new(),
+            stdin: None,
+            timeout: None,
+            progress_label: None,
+            stdout_handler: None,
+            stderr_handler: None,
+        }
+    }
+
+    pub fn get_version(&self) -> Result<String> {
+        let command = self
+            .get_command("version")
+            .with_args(vec!["-json"])
+            .run()?;
+        let v: Value = serde_json::from_str(&command.get_stdout().unwrap_or_default())?;
+        // OpenTofu keeps Terraform's key, but accept its own name as well.
+        v["terraform_version"]
+            .as_str()
+            .or(v["tofu_version"].as_str())
+            .map(String::from)
+            .ok_or_else(|| anyhow!("{} did not report a version.", self.name))
+    }
 }
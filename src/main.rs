@@ -1,48 +1,147 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod cli;
 mod commands;
 mod output_tester;
 mod resource;
 
-use std::{env::var, path::Path};
+use std::{
+    env::var,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use cli::get_app_cli;
+use commands::{cancel_commands, is_cancelled, kill_commands, set_az_cli_binary};
 use env_logger::Env;
-use output_tester::{ApplicationConfig, OutputTester};
+use log::{info, warn};
+use output_tester::{
+    run_suites, write_json_artifact, write_yaml_artifact, ApplicationConfig, CommandOptions,
+    OutputTester, RunReport, Stages, CONFIG_FILE, REPORT_FILE,
+};
 
 lazy_static! {
     pub static ref VERSION: String = format!("v{}", env!("CARGO_PKG_VERSION"));
-    //pub static ref VERBOSE: bool = get_app_cli(&VERSION).get_matches().is_present("verbose");
 }
 
 fn main() -> Result<()> {
-    let config_file_path = Path::new("tests/parameters.yaml");
-    let application_config = ApplicationConfig::load(config_file_path)?;
+    let matches = get_app_cli(&VERSION).get_matches();
+    let config_file_path = Path::new(matches.get_one::<String>("config").unwrap());
+    let mut application_config = ApplicationConfig::load(config_file_path)?;
 
     let log_level = application_config.log_level.as_deref().unwrap_or("warn");
     env_logger::init_from_env(
         Env::default().default_filter_or(log_level)
     );
 
+    let profile = matches.get_one::<String>("profile").cloned();
+    if let Some(profile_name) = &profile {
+        info!("Applying profile {}.", profile_name);
+        application_config = application_config.apply_profile(profile_name)?;
+    }
+
     if let Ok(only_config) = var("CONFIG") {
-        let print_config = match only_config.to_lowercase().as_str() {
-            "true" | "1" => true,
-            _ => false,
-        };
-        
+        let print_config = matches!(only_config.to_lowercase().as_str(), "true" | "1");
+
         if print_config {
             println!("{:#?}", &application_config);
             return Ok(());
         }
     }
 
-    OutputTester::new()
+    let suite_names: Vec<String> = matches
+        .get_many::<String>("suite")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let suites = application_config.select_suites(&suite_names)?;
+
+    let artifacts_directory = matches
+        .get_one::<PathBuf>("artifacts-dir")
+        .cloned()
+        .or(application_config.artifacts_directory.as_ref().map(PathBuf::from));
+    let compare_only = matches.get_flag("compare-only");
+    if compare_only && artifacts_directory.is_none() {
+        return Err(anyhow!("--compare-only needs an artifacts directory to read from."));
+    }
+    let refresh_only = matches.get_flag("refresh-only");
+    let stages = Stages {
+        authenticate: !compare_only && !matches.get_flag("skip-auth"),
+        terraform_init: !matches.get_flag("skip-init"),
+        bicep: !matches.get_flag("terraform-only") && !refresh_only,
+        terraform: !matches.get_flag("bicep-only"),
+        from_cache: compare_only,
+        refresh_only,
+    };
+
+    if let Some(directory) = artifacts_directory.as_ref().filter(|_| !compare_only) {
+        write_yaml_artifact(directory, CONFIG_FILE, &application_config);
+    }
+
+    let command_timeout = match matches.get_one::<Duration>("timeout") {
+        Some(timeout) => Some(*timeout),
+        None => application_config
+            .command_timeout
+            .as_deref()
+            .map(humantime::parse_duration)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid commandTimeout in the config: {}", e))?,
+    };
+
+    ctrlc::set_handler(|| {
+        if cancel_commands() {
+            warn!("Cancelling.  Press Ctrl-C again to exit immediately.");
+        } else {
+            kill_commands();
+            process::exit(130);
+        }
+    })?;
+
+    if let Some(az_cli_binary) = &application_config.az_cli_binary {
+        set_az_cli_binary(az_cli_binary)?;
+    }
+
+    let mut base_tester = OutputTester::new();
+    base_tester
         .set_application_config(application_config)
+        .set_stages(stages)
+        .set_command_options(CommandOptions {
+            verbose: matches.get_flag("verbose"),
+            show_progress: matches.get_flag("progress"),
+            timeout: command_timeout,
+        })
+        .set_artifacts_directory(artifacts_directory.clone())
         .authenticate_azure_cli()
-        .set_deployment_parameters()
-        .execute_bicep_whatif()
-        .init_terraform_environment()
-        .execute_terraform_plan()
-        .compare_bicep_whatif_and_terraform_plan()
+        .collect_tool_versions();
+
+    let jobs = *matches.get_one::<usize>("jobs").unwrap();
+    let runs = suites.iter().flat_map(|suite| suite.expand_matrix()).collect();
+    let mut report = RunReport {
+        profile,
+        ..Default::default()
+    };
+    for suite_report in run_suites(&base_tester, runs, jobs) {
+        report.add(suite_report);
+    }
+
+    if let Some(directory) = &artifacts_directory {
+        write_json_artifact(directory, REPORT_FILE, &report);
+    }
+    println!("Results: {} ", serde_json::to_string_pretty(&report)?);
+
+    if is_cancelled() {
+        return Err(anyhow!("Cancelled."));
+    }
+    if report.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} of {} suite runs did not pass.",
+            report.failed + report.errors,
+            report.suites.len()
+        ))
+    }
 }
@@ -0,0 +1,104 @@
+use crate::commands::AzAccountInfo;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path, time::SystemTime};
+
+pub const BICEP_WHATIF_FILE: &str = "bicep-whatif.json";
+pub const BICEP_WHATIF_STDERR_FILE: &str = "bicep-whatif.stderr.log";
+pub const TERRAFORM_PLAN_FILE: &str = "terraform-plan.jsonl";
+pub const TERRAFORM_STRUCTURED_PLAN_FILE: &str = "terraform-plan.json";
+pub const CONFIG_FILE: &str = "config.yaml";
+pub const REPORT_FILE: &str = "report.json";
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// One external command run as part of a suite run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommandRecord {
+    pub command: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub exit_code: Option<i32>,
+}
+
+impl CommandRecord {
+    pub fn new(command: String, started_at: SystemTime, exit_code: Option<i32>) -> Self {
+        CommandRecord {
+            command,
+            started_at: format_timestamp(started_at),
+            finished_at: format_timestamp(SystemTime::now()),
+            exit_code,
+        }
+    }
+}
+
+/// What ran, when, with which tools and against which subscription.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunManifest {
+    pub run: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub tool_versions: BTreeMap<String, String>,
+    pub subscription: Option<AzAccountInfo>,
+    pub commands: Vec<CommandRecord>,
+}
+
+pub fn format_timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Writes `contents` to `file_name` under `directory`, creating the directory
+/// when needed.  Failures are logged rather than returned so a broken
+/// artifacts directory never fails the comparison itself.
+pub fn write_artifact(directory: &Path, file_name: &str, contents: &str) {
+    let result = fs::create_dir_all(directory)
+        .and_then(|_| fs::write(directory.join(file_name), contents));
+    if let Err(e) = result {
+        error!("Failed to write {} to {}: {}", file_name, directory.display(), e);
+    }
+}
+
+pub fn write_json_artifact<T: Serialize>(directory: &Path, file_name: &str, value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(contents) => write_artifact(directory, file_name, &contents),
+        Err(e) => error!("Failed to serialize {}: {}", file_name, e),
+    }
+}
+
+pub fn write_yaml_artifact<T: Serialize>(directory: &Path, file_name: &str, value: &T) {
+    match serde_yaml::to_string(value) {
+        Ok(contents) => write_artifact(directory, file_name, &contents),
+        Err(e) => error!("Failed to serialize {}: {}", file_name, e),
+    }
+}
+
+#[cfg(test)]
+mod artifacts_test {
+    use super::*;
+
+    #[test]
+    fn test_write_artifacts() {
+        let directory = std::env::temp_dir()
+            .join(format!("infra-test-write-{}", std::process::id()))
+            .join("suite");
+        let started_at = SystemTime::UNIX_EPOCH;
+        let manifest = RunManifest {
+            run: "default".to_string(),
+            started_at: format_timestamp(started_at),
+            commands: vec![CommandRecord::new("terraform plan".to_string(), started_at, Some(1))],
+            ..Default::default()
+        };
+        write_json_artifact(&directory, MANIFEST_FILE, &manifest);
+        write_yaml_artifact(&directory, CONFIG_FILE, &BTreeMap::from([("name", "default")]));
+
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(directory.join(MANIFEST_FILE)).unwrap()).unwrap();
+        let config = fs::read_to_string(directory.join(CONFIG_FILE)).unwrap();
+        fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+
+        assert_eq!(written["run"], "default");
+        assert_eq!(written["started_at"], "1970-01-01T00:00:00Z");
+        assert_eq!(written["subscription"], serde_json::Value::Null);
+        assert_eq!(written["commands"][0]["command"], "terraform plan");
+        assert_eq!(written["commands"][0]["exit_code"], 1);
+        assert_eq!(config.trim(), "name: default");
+    }
+}
@@ -0,0 +1,761 @@
+use super::{ResourceResult, TagPolicy, VersionConstraint};
+use crate::{
+    commands::{AzureAuthentication, AzureCloud},
+    resource::ResourceClass,
+};
+use anyhow::{anyhow, Result};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::Read,
+    path::Path,
+    time::Duration,
+};
+
+pub const DEFAULT_SUITE_NAME: &str = "default";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApplicationConfig {
+    pub log_level: Option<String>,
+    #[serde(rename = "parameters", default)]
+    pub infra_parameters: Vec<InfraParameters>,
+    #[serde(rename = "terraformPath")]
+    pub terraform_path: Option<String>,
+    #[serde(rename = "bicepPath")]
+    pub bicep_path: Option<String>,
+    /// The Terraform compatible CLI to run, e.g. `tofu` or a path to a pinned
+    /// Terraform binary.  Suites can set their own.
+    #[serde(rename = "terraformBinary", default, skip_serializing_if = "Option::is_none")]
+    pub terraform_binary: Option<String>,
+    /// The Azure CLI to run instead of the `az` found on `PATH`.
+    #[serde(rename = "azCliBinary", default, skip_serializing_if = "Option::is_none")]
+    pub az_cli_binary: Option<String>,
+    /// The resources both tools should plan.  Each entry matches one
+    /// resource, so a Terraform resource with several instances, or a
+    /// module with several resources, needs an entry for each.
+    #[serde(rename = "expectedResults", default)]
+    pub expected_results: Vec<ResourceResult>,
+    #[serde(rename = "artifactsDirectory", default, skip_serializing_if = "Option::is_none")]
+    pub artifacts_directory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication: Option<AzureAuthentication>,
+    #[serde(rename = "subscriptionId", default, skip_serializing_if = "Option::is_none")]
+    pub subscription_id: Option<String>,
+    #[serde(rename = "subscriptionName", default, skip_serializing_if = "Option::is_none")]
+    pub subscription_name: Option<String>,
+    #[serde(rename = "tenantId", default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub cloud: AzureCloud,
+    /// How long any single `az` or `terraform` command may run, e.g. `30m`.
+    #[serde(rename = "commandTimeout", default, skip_serializing_if = "Option::is_none")]
+    pub command_timeout: Option<String>,
+    #[serde(rename = "typeMappings", default, skip_serializing_if = "HashMap::is_empty")]
+    pub type_mappings: HashMap<String, String>,
+    #[serde(rename = "requiredVersions", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub required_versions: BTreeMap<String, VersionConstraint>,
+    /// Fail a suite run on Bicep or Terraform warnings, not only on errors.
+    #[serde(rename = "failOnWarnings", default)]
+    pub fail_on_warnings: bool,
+    /// The kinds of Terraform resource to compare.  Helper resources and
+    /// data sources have no Bicep counterpart, so only `azure` by default.
+    #[serde(rename = "resourceClasses", default = "default_resource_classes")]
+    pub resource_classes: BTreeSet<ResourceClass>,
+    /// Tags matched resources must carry, whether tags the tools set
+    /// differently fail a run, and tags to leave out of the comparison.
+    #[serde(rename = "tagPolicy", default)]
+    pub tag_policy: TagPolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<DeploymentScope>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matrix: Vec<MatrixAxis>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suites: Vec<SuiteConfig>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, ProfileConfig>,
+}
+
+impl Default for ApplicationConfig {
+    fn default() -> Self {
+        ApplicationConfig {
+            log_level: Some("info".to_string()),
+            infra_parameters: Vec::new(),
+            terraform_path: Some("./infra/terraform".to_string()),
+            bicep_path: Some("./infra/bicep".to_string()),
+            terraform_binary: None,
+            az_cli_binary: None,
+            expected_results: Vec::new(),
+            artifacts_directory: None,
+            authentication: None,
+            subscription_id: None,
+            subscription_name: None,
+            tenant_id: None,
+            cloud: AzureCloud::default(),
+            command_timeout: None,
+            type_mappings: HashMap::new(),
+            required_versions: BTreeMap::new(),
+            fail_on_warnings: false,
+            resource_classes: default_resource_classes(),
+            tag_policy: TagPolicy::default(),
+            scope: None,
+            matrix: Vec::new(),
+            suites: Vec::new(),
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+fn default_resource_classes() -> BTreeSet<ResourceClass> {
+    BTreeSet::from([ResourceClass::Azure])
+}
+
+impl ApplicationConfig {
+    pub fn load(path: &Path) -> Result<ApplicationConfig> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        match serde_yaml::from_str::<ApplicationConfig>(&contents) {
+            Ok(s) => {
+                s.check_suite_names()?;
+                Ok(s)
+            }
+            Err(e) => {
+                error!("Error parsing YAML {}", e);
+                Ok(ApplicationConfig::default())
+            }
+        }
+    }
+
+    /// Fails when two suites share a name, as their runs would write to the
+    /// same artifacts directory and plan files.
+    fn check_suite_names(&self) -> Result<()> {
+        for (i, suite) in self.suites.iter().enumerate() {
+            if self.suites[..i].iter().any(|x| x.name == suite.name) {
+                return Err(anyhow!("Suite '{}' is defined more than once in the config.", suite.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The config with the named profile, and every profile it inherits
+    /// from, layered over the top level settings.
+    pub fn apply_profile(&self, name: &str) -> Result<ApplicationConfig> {
+        let mut chain: Vec<&ProfileConfig> = Vec::new();
+        let mut visited: Vec<&str> = Vec::new();
+        let mut next = Some(name);
+        while let Some(profile_name) = next {
+            if visited.contains(&profile_name) {
+                return Err(anyhow!(
+                    "Profile '{}' inherits from itself through {}.",
+                    profile_name,
+                    visited.join(" -> ")
+                ));
+            }
+            let profile = self
+                .profiles
+                .get(profile_name)
+                .ok_or_else(|| anyhow!("Profile '{}' is not defined in the config.", profile_name))?;
+            visited.push(profile_name);
+            chain.push(profile);
+            next = profile.inherits.as_deref();
+        }
+
+        let mut config = self.clone();
+        for profile in chain.iter().rev() {
+            profile.apply_to(&mut config);
+        }
+        Ok(config)
+    }
+
+    /// The suite described by the top level of the config file.
+    pub fn base_suite(&self) -> SuiteConfig {
+        SuiteConfig {
+            name: DEFAULT_SUITE_NAME.to_string(),
+            infra_parameters: self.infra_parameters.clone(),
+            terraform_path: self.terraform_path.clone(),
+            bicep_path: self.bicep_path.clone(),
+            terraform_binary: self.terraform_binary.clone(),
+            expected_results: self.expected_results.clone(),
+            scope: self.scope.clone(),
+            matrix: self.matrix.clone(),
+        }
+    }
+
+    /// All configured suites.  Without a `suites` list the top level of the
+    /// config is the only suite; otherwise it supplies the defaults for any
+    /// settings a suite leaves out.
+    pub fn get_suites(&self) -> Vec<SuiteConfig> {
+        if self.suites.is_empty() {
+            return vec![self.base_suite()];
+        }
+        let base = self.base_suite();
+        self.suites
+            .iter()
+            .map(|suite| suite.inherit_from(&base))
+            .collect()
+    }
+
+    /// The suites matching `names`, in config order, or every suite when no
+    /// names are given.
+    pub fn select_suites(&self, names: &[String]) -> Result<Vec<SuiteConfig>> {
+        let suites = self.get_suites();
+        if names.is_empty() {
+            return Ok(suites);
+        }
+        if let Some(unknown) = names
+            .iter()
+            .find(|name| !suites.iter().any(|suite| &suite.name == *name))
+        {
+            return Err(anyhow!("Suite '{}' is not defined in the config.", unknown));
+        }
+        Ok(suites
+            .into_iter()
+            .filter(|suite| names.contains(&suite.name))
+            .collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct SuiteConfig {
+    pub name: String,
+    #[serde(rename = "parameters", default)]
+    pub infra_parameters: Vec<InfraParameters>,
+    #[serde(rename = "terraformPath")]
+    pub terraform_path: Option<String>,
+    #[serde(rename = "bicepPath")]
+    pub bicep_path: Option<String>,
+    #[serde(rename = "terraformBinary", default, skip_serializing_if = "Option::is_none")]
+    pub terraform_binary: Option<String>,
+    /// The resources both tools should plan.  Each entry matches one
+    /// resource, so a Terraform resource with several instances, or a
+    /// module with several resources, needs an entry for each.
+    #[serde(rename = "expectedResults", default)]
+    pub expected_results: Vec<ResourceResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<DeploymentScope>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matrix: Vec<MatrixAxis>,
+}
+
+impl SuiteConfig {
+    /// One run per cell of the cartesian product of the matrix axes, each
+    /// with the cell's values layered over the suite parameters.  A suite
+    /// without a matrix yields a single run.
+    pub fn expand_matrix(&self) -> Vec<SuiteRun> {
+        let mut cells: Vec<BTreeMap<String, String>> = vec![BTreeMap::new()];
+        let mut cell_parameters: Vec<Vec<InfraParameters>> = vec![Vec::new()];
+        for axis in &self.matrix {
+            if axis.values.is_empty() {
+                warn!("Matrix axis {} has no values. Ignoring it.", axis.get_name());
+                continue;
+            }
+            let mut next_cells = Vec::new();
+            let mut next_parameters = Vec::new();
+            for (cell, parameters) in cells.iter().zip(cell_parameters.iter()) {
+                for value in &axis.values {
+                    let mut cell = cell.clone();
+                    cell.insert(axis.get_name().to_string(), value.clone());
+                    let mut parameters = parameters.clone();
+                    parameters.push(axis.get_parameter(value));
+                    next_cells.push(cell);
+                    next_parameters.push(parameters);
+                }
+            }
+            cells = next_cells;
+            cell_parameters = next_parameters;
+        }
+
+        cells
+            .into_iter()
+            .zip(cell_parameters)
+            .map(|(matrix_cell, parameters)| {
+                let mut suite = self.clone();
+                merge_parameters(&mut suite.infra_parameters, &parameters);
+                SuiteRun { suite, matrix_cell }
+            })
+            .collect()
+    }
+
+    fn inherit_from(&self, base: &SuiteConfig) -> SuiteConfig {
+        let mut suite = self.clone();
+        if suite.infra_parameters.is_empty() {
+            suite.infra_parameters = base.infra_parameters.clone();
+        }
+        if suite.terraform_path.is_none() {
+            suite.terraform_path = base.terraform_path.clone();
+        }
+        if suite.bicep_path.is_none() {
+            suite.bicep_path = base.bicep_path.clone();
+        }
+        if suite.terraform_binary.is_none() {
+            suite.terraform_binary = base.terraform_binary.clone();
+        }
+        if suite.expected_results.is_empty() {
+            suite.expected_results = base.expected_results.clone();
+        }
+        if suite.scope.is_none() {
+            suite.scope = base.scope.clone();
+        }
+        if suite.matrix.is_empty() {
+            suite.matrix = base.matrix.clone();
+        }
+        suite
+    }
+}
+
+/// A single suite invocation, identified by the suite name and the matrix
+/// values it was run with.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SuiteRun {
+    pub suite: SuiteConfig,
+    pub matrix_cell: BTreeMap<String, String>,
+}
+
+impl SuiteRun {
+    pub fn get_name(&self) -> String {
+        if self.matrix_cell.is_empty() {
+            return self.suite.name.clone();
+        }
+        let cell = self
+            .matrix_cell
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(",");
+        format!("{}[{}]", self.suite.name, cell)
+    }
+
+    /// The run name with anything that is awkward in a path replaced.
+    pub fn get_directory_name(&self) -> String {
+        self.get_name()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' | '=' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+}
+
+/// The parts of the pipeline to run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Stages {
+    pub authenticate: bool,
+    pub terraform_init: bool,
+    pub bicep: bool,
+    pub terraform: bool,
+    /// Compare the outputs saved by an earlier run instead of running the tools.
+    pub from_cache: bool,
+    /// Only refresh the Terraform state and report drift, without comparing.
+    pub refresh_only: bool,
+}
+
+impl Default for Stages {
+    fn default() -> Self {
+        Stages {
+            authenticate: true,
+            terraform_init: true,
+            bicep: true,
+            terraform: true,
+            from_cache: false,
+            refresh_only: false,
+        }
+    }
+}
+
+/// How every `az` and `terraform` command is run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct CommandOptions {
+    /// Log command lines and output at info rather than debug level.
+    pub verbose: bool,
+    /// Report progress on stderr while commands run.
+    pub show_progress: bool,
+    /// Stop any command still running after this long.
+    pub timeout: Option<Duration>,
+}
+
+/// One dimension of a parameter matrix.  Every value is passed to both tools
+/// under the configured names, like an entry in `parameters`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct MatrixAxis {
+    #[serde(rename = "bicepName")]
+    pub bicep_name: Option<String>,
+    #[serde(rename = "terraformName")]
+    pub terraform_name: Option<String>,
+    pub values: Vec<String>,
+}
+
+impl MatrixAxis {
+    pub fn get_name(&self) -> &str {
+        self.bicep_name
+            .as_deref()
+            .or(self.terraform_name.as_deref())
+            .unwrap_or_default()
+    }
+
+    fn get_parameter(&self, value: &str) -> InfraParameters {
+        InfraParameters {
+            bicep_name: self.bicep_name.clone(),
+            terraform_name: self.terraform_name.clone(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Overrides for one environment.  Parameters override the entry with the
+/// same Bicep or Terraform name, both at the top level and in every suite,
+/// keeping any name they leave out, and are added when no entry matches.  Expected results and scope replace the top
+/// level values, so they reach every suite that does not set its own.  The
+/// cloud replaces the top level cloud and type mappings are merged over the
+/// top level ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherits: Option<String>,
+    #[serde(rename = "parameters", default)]
+    pub infra_parameters: Vec<InfraParameters>,
+    #[serde(rename = "expectedResults", default, skip_serializing_if = "Option::is_none")]
+    pub expected_results: Option<Vec<ResourceResult>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<DeploymentScope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud: Option<AzureCloud>,
+    #[serde(rename = "typeMappings", default, skip_serializing_if = "HashMap::is_empty")]
+    pub type_mappings: HashMap<String, String>,
+}
+
+impl ProfileConfig {
+    fn apply_to(&self, config: &mut ApplicationConfig) {
+        merge_parameters(&mut config.infra_parameters, &self.infra_parameters);
+        for suite in config
+            .suites
+            .iter_mut()
+            .filter(|suite| !suite.infra_parameters.is_empty())
+        {
+            merge_parameters(&mut suite.infra_parameters, &self.infra_parameters);
+        }
+        if let Some(expected_results) = &self.expected_results {
+            config.expected_results = expected_results.clone();
+        }
+        if let Some(scope) = &self.scope {
+            config.scope = Some(scope.clone());
+        }
+        if let Some(cloud) = self.cloud {
+            config.cloud = cloud;
+        }
+        config
+            .type_mappings
+            .extend(self.type_mappings.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+}
+
+/// Merges `overrides` into `parameters`.  An override of an existing entry
+/// sets its value and any name it gives, so giving only the Bicep name still
+/// passes the new value to Terraform.
+fn merge_parameters(parameters: &mut Vec<InfraParameters>, overrides: &[InfraParameters]) {
+    for entry in overrides {
+        match parameters.iter_mut().find(|x| x.is_same_parameter(entry)) {
+            Some(existing) => {
+                if entry.bicep_name.is_some() {
+                    existing.bicep_name = entry.bicep_name.clone();
+                }
+                if entry.terraform_name.is_some() {
+                    existing.terraform_name = entry.terraform_name.clone();
+                }
+                existing.value = entry.value.clone();
+            }
+            None => parameters.push(entry.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct InfraParameters {
+    #[serde(rename = "bicepName")]
+    pub bicep_name: Option<String>,
+    #[serde(rename = "terraformName")]
+    pub terraform_name: Option<String>,
+    pub value: String,
+}
+
+impl InfraParameters {
+    fn is_same_parameter(&self, other: &InfraParameters) -> bool {
+        (self.bicep_name.is_some() && self.bicep_name == other.bicep_name)
+            || (self.terraform_name.is_some() && self.terraform_name == other.terraform_name)
+    }
+}
+
+/// Where the Bicep template is deployed, which decides the
+/// `az deployment <scope> what-if` variant that gets run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "level", rename_all = "camelCase")]
+pub enum DeploymentScope {
+    #[default]
+    Subscription,
+    ResourceGroup {
+        #[serde(rename = "resourceGroup")]
+        resource_group: String,
+    },
+    ManagementGroup {
+        #[serde(rename = "managementGroupId")]
+        management_group_id: String,
+    },
+    Tenant,
+}
+
+impl DeploymentScope {
+    pub fn get_whatif_arguments(&self, location: &str) -> Vec<String> {
+        let arguments = match self {
+            DeploymentScope::Subscription => vec!["sub", "what-if", "--location", location],
+            DeploymentScope::ResourceGroup { resource_group } => {
+                vec!["group", "what-if", "--resource-group", resource_group]
+            }
+            DeploymentScope::ManagementGroup {
+                management_group_id,
+            } => vec![
+                "mg",
+                "what-if",
+                "--management-group-id",
+                management_group_id,
+                "--location",
+                location,
+            ],
+            DeploymentScope::Tenant => vec!["tenant", "what-if", "--location", location],
+        };
+        arguments.into_iter().map(String::from).collect()
+    }
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    #[test]
+    fn test_single_suite_from_top_level() {
+        let config = ApplicationConfig::load(Path::new("tests/parameters.yaml")).unwrap();
+        let suites = config.get_suites();
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suites[0].name, DEFAULT_SUITE_NAME);
+        assert_eq!(suites[0].infra_parameters.len(), 4);
+        assert_eq!(suites[0].expected_results.len(), 3);
+    }
+
+    #[test]
+    fn test_resource_classes() {
+        let config = ApplicationConfig::load(Path::new("tests/parameters.yaml")).unwrap();
+        assert_eq!(config.resource_classes, BTreeSet::from([ResourceClass::Azure]));
+
+        let config: ApplicationConfig = serde_yaml::from_str("resourceClasses: [azure, helper]").unwrap();
+        assert!(config.resource_classes.contains(&ResourceClass::Helper));
+        assert!(!config.resource_classes.contains(&ResourceClass::Data));
+    }
+
+    #[test]
+    fn test_suites_inherit_from_top_level() {
+        let yaml = r#"
+        terraformPath: ./infra/terraform
+        bicepPath: ./infra/bicep
+        parameters:
+          - bicepName: location
+            terraformName: location
+            value: eastus
+        suites:
+          - name: network
+            bicepPath: ./network/bicep
+            scope:
+              level: resourceGroup
+              resourceGroup: rg-network
+            expectedResults:
+              - type: virtual_network
+          - name: cluster
+            terraformBinary: tofu
+            expectedResults:
+              - type: kubernetes_cluster
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        let suites = config.get_suites();
+        assert_eq!(suites.len(), 2);
+        assert_eq!(suites[0].bicep_path, Some("./network/bicep".to_string()));
+        assert_eq!(suites[0].terraform_path, Some("./infra/terraform".to_string()));
+        assert_eq!(suites[0].infra_parameters.len(), 1);
+        assert_eq!(
+            suites[0].scope,
+            Some(DeploymentScope::ResourceGroup {
+                resource_group: "rg-network".to_string()
+            })
+        );
+        assert_eq!(suites[1].scope, None);
+        assert_eq!(suites[0].terraform_binary, None);
+        assert_eq!(suites[1].terraform_binary, Some("tofu".to_string()));
+
+        let selected = config.select_suites(&["cluster".to_string()]).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name, "cluster");
+        assert!(config.select_suites(&["missing".to_string()]).is_err());
+        assert!(config.check_suite_names().is_ok());
+
+        let yaml = r#"
+        suites:
+          - name: network
+          - name: network
+            bicepPath: ./other/bicep
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.check_suite_names().is_err());
+    }
+
+    #[test]
+    fn test_profile_overrides_base() {
+        let yaml = r#"
+        parameters:
+          - bicepName: environmentName
+            value: nevermore
+          - bicepName: location
+            terraformName: location
+            value: eastus
+        expectedResults:
+          - type: resource_group
+        profiles:
+          shared:
+            parameters:
+              - bicepName: location
+                terraformName: location
+                value: westus2
+          prod:
+            inherits: shared
+            parameters:
+              - bicepName: environmentName
+                value: prod
+              - terraformName: sku
+                value: premium
+            expectedResults:
+              - type: resource_group
+              - type: key_vault
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        let prod = config.apply_profile("prod").unwrap();
+        assert_eq!(prod.infra_parameters.len(), 3);
+        assert_eq!(prod.infra_parameters[0].value, "prod");
+        assert_eq!(prod.infra_parameters[1].value, "westus2");
+        assert_eq!(prod.infra_parameters[2].terraform_name, Some("sku".to_string()));
+        assert_eq!(prod.expected_results.len(), 2);
+
+        let shared = config.apply_profile("shared").unwrap();
+        assert_eq!(shared.expected_results.len(), 1);
+        assert!(config.apply_profile("missing").is_err());
+    }
+
+    #[test]
+    fn test_override_keeps_other_name() {
+        let yaml = r#"
+        parameters:
+          - bicepName: location
+            terraformName: region
+            value: eastus
+        expectedResults:
+          - type: resource_group
+        matrix:
+          - bicepName: location
+            values: [westus2]
+        profiles:
+          west:
+            parameters:
+              - terraformName: region
+                value: westeurope
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        let expected = |value: &str| InfraParameters {
+            bicep_name: Some("location".to_string()),
+            terraform_name: Some("region".to_string()),
+            value: value.to_string(),
+        };
+        let runs = config.get_suites()[0].expand_matrix();
+        assert_eq!(runs[0].suite.infra_parameters, vec![expected("westus2")]);
+        let west = config.apply_profile("west").unwrap();
+        assert_eq!(west.infra_parameters, vec![expected("westeurope")]);
+    }
+
+    #[test]
+    fn test_profile_sets_cloud() {
+        let yaml = r#"
+        typeMappings:
+          Microsoft.Web/sites: app_service
+        profiles:
+          gov:
+            cloud: AzureUSGovernment
+            typeMappings:
+              Microsoft.Cdn/profiles: ""
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.cloud, AzureCloud::Public);
+        let gov = config.apply_profile("gov").unwrap();
+        assert_eq!(gov.cloud, AzureCloud::UsGovernment);
+        assert_eq!(gov.cloud.get_terraform_environment(), "usgovernment");
+        assert_eq!(gov.type_mappings.len(), 2);
+    }
+
+    #[test]
+    fn test_profile_inheritance_cycle() {
+        let yaml = r#"
+        profiles:
+          dev:
+            inherits: test
+          test:
+            inherits: dev
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.apply_profile("dev").is_err());
+    }
+
+    #[test]
+    fn test_expand_matrix() {
+        let yaml = r#"
+        parameters:
+          - bicepName: location
+            terraformName: location
+            value: eastus
+          - bicepName: environmentName
+            value: nevermore
+        matrix:
+          - bicepName: location
+            terraformName: location
+            values: [eastus, westeurope]
+          - terraformName: enable_monitoring
+            values: ["true", "false"]
+        "#;
+        let config: ApplicationConfig = serde_yaml::from_str(yaml).unwrap();
+        let runs = config.get_suites()[0].expand_matrix();
+        assert_eq!(runs.len(), 4);
+        assert_eq!(runs[2].matrix_cell.get("location"), Some(&"westeurope".to_string()));
+        assert_eq!(runs[2].matrix_cell.get("enable_monitoring"), Some(&"true".to_string()));
+        assert_eq!(runs[2].suite.infra_parameters.len(), 3);
+        assert_eq!(runs[2].suite.infra_parameters[0].value, "westeurope");
+
+        let single = config.base_suite();
+        let single = SuiteConfig {
+            matrix: Vec::new(),
+            ..single
+        };
+        let runs = single.expand_matrix();
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].matrix_cell.is_empty());
+    }
+
+    #[test]
+    fn test_whatif_arguments_for_scope() {
+        let scope = DeploymentScope::ResourceGroup {
+            resource_group: "rg-test".to_string(),
+        };
+        assert_eq!(
+            scope.get_whatif_arguments("eastus"),
+            vec!["group", "what-if", "--resource-group", "rg-test"]
+        );
+        assert_eq!(
+            DeploymentScope::default().get_whatif_arguments("eastus"),
+            vec!["sub", "what-if", "--location", "eastus"]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use super::TERRAFORM_PLAN_FILE;
+use crate::resource::{MalformedLine, TerraformDiagnostic, TerraformSourcePosition};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref BICEP_DIAGNOSTIC_REGEX: Regex = Regex::new(
+        r"^(?:ERROR: |WARNING: )?(?P<file>.+?)\((?P<line>\d+),(?P<column>\d+)\) : (?P<severity>Error|Warning|Info) (?P<code>[^:]+): (?P<message>.*)$"
+    )
+    .unwrap();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceRange {
+    pub start: SourcePosition,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<SourcePosition>,
+}
+
+/// A compiler or provider message about one of the templates, from the Bicep
+/// build output or a Terraform `diagnostic` event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub tool: String,
+    pub severity: Severity,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<SourceRange>,
+}
+
+impl From<&TerraformDiagnostic> for Diagnostic {
+    fn from(diagnostic: &TerraformDiagnostic) -> Self {
+        let position = |x: &TerraformSourcePosition| SourcePosition {
+            line: x.line,
+            column: x.column,
+        };
+        Diagnostic {
+            tool: "terraform".to_string(),
+            severity: match diagnostic.severity.as_str() {
+                "error" => Severity::Error,
+                "warning" => Severity::Warning,
+                _ => Severity::Info,
+            },
+            code: None,
+            summary: diagnostic.summary.clone(),
+            detail: Some(diagnostic.detail.clone()).filter(|x| !x.is_empty()),
+            address: diagnostic.address.clone(),
+            file: diagnostic.range.as_ref().map(|x| x.filename.clone()),
+            range: diagnostic.range.as_ref().map(|x| SourceRange {
+                start: position(&x.start),
+                end: Some(position(&x.end)),
+            }),
+        }
+    }
+}
+
+/// A line of `terraform plan -json` output that was not a JSON event, such as
+/// a wrapper banner or a provider panic.
+impl From<&MalformedLine> for Diagnostic {
+    fn from(line: &MalformedLine) -> Self {
+        Diagnostic {
+            tool: "terraform".to_string(),
+            severity: Severity::Info,
+            code: None,
+            summary: "Terraform Plan output line is not a JSON event".to_string(),
+            detail: Some(format!("{} ({})", line.content, line.error)),
+            address: None,
+            file: Some(TERRAFORM_PLAN_FILE.to_string()),
+            range: Some(SourceRange {
+                start: SourcePosition {
+                    line: line.line_number as u32,
+                    column: 1,
+                },
+                end: None,
+            }),
+        }
+    }
+}
+
+/// Picks the Bicep build diagnostics out of the Azure CLI's stderr, which
+/// look like `main.bicep(3,7) : Warning no-unused-params: Parameter "foo" ...`.
+pub fn parse_bicep_diagnostics(stderr: &str) -> Vec<Diagnostic> {
+    stderr
+        .lines()
+        .filter_map(|line| BICEP_DIAGNOSTIC_REGEX.captures(line.trim_end()))
+        .map(|x| Diagnostic {
+            tool: "bicep".to_string(),
+            severity: match &x["severity"] {
+                "Error" => Severity::Error,
+                "Warning" => Severity::Warning,
+                _ => Severity::Info,
+            },
+            code: Some(x["code"].to_string()),
+            summary: x["message"].to_string(),
+            detail: None,
+            address: None,
+            file: Some(x["file"].to_string()),
+            range: Some(SourceRange {
+                start: SourcePosition {
+                    line: x["line"].parse().unwrap_or_default(),
+                    column: x["column"].parse().unwrap_or_default(),
+                },
+                end: None,
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod diagnostics_test {
+    use super::*;
+
+    #[test]
+    fn test_parse_bicep_diagnostics() {
+        let stderr = r#"WARNING: A new Bicep release is available: v0.26.54.
+/src/infra/main.bicep(3,7) : Warning no-unused-params: Parameter "foo" is declared but never used. [https://aka.ms/bicep/linter/no-unused-params]
+ERROR: /src/infra/modules/aks.bicep(12,5) : Error BCP037: The property "sku" is not allowed on objects of type "ManagedClusterProperties".
+"#;
+        let diagnostics = parse_bicep_diagnostics(stderr);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].code, Some("no-unused-params".to_string()));
+        assert_eq!(diagnostics[0].file, Some("/src/infra/main.bicep".to_string()));
+        assert_eq!(diagnostics[0].range.unwrap().start, SourcePosition { line: 3, column: 7 });
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(diagnostics[1].code, Some("BCP037".to_string()));
+    }
+}
@@ -1,79 +1,155 @@
+mod artifacts;
+mod config;
+mod diagnostics;
+mod progress;
+mod report;
+mod role_assignments;
+mod runner;
+mod subscription;
+mod tags;
+mod versions;
+
 use crate::{
-    commands::{set_azure_environment, get_az_cli_command, get_terraform_command},
-    resource::{AzureResourceChange, TerraformPlanStep, TerraformResourceChange},
+    commands::{
+        get_az_cli_command, get_az_cli_version, get_bicep_version, set_azure_environment, AzAccountInfo, Command,
+        LineHandler, TerraformCli,
+    },
+    resource::{
+        get_planned_values, get_terraform_canonical_resources, get_unknown_values, AzureResourceChange, CanonicalResource,
+        ResourceTypeMap, TerraformPlanParser, TerraformResource, TerraformResourceChange,
+    },
 };
 use anyhow::{anyhow, Result};
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
-    fs::File,
-    io::Read,
+    collections::{BTreeMap, HashMap},
+    env, fs,
     path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::SystemTime,
 };
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-pub struct ApplicationConfig {
-    pub log_level: Option<String>,
-    #[serde(rename = "parameters")]
-    pub infra_parameters: Vec<InfraParameters>,
-    #[serde(rename = "terraformPath")]
-    pub terraform_path: Option<String>,
-    #[serde(rename = "bicepPath")]
-    pub bicep_path: Option<String>,
-    #[serde(rename = "expectedResults")]
-    pub expected_results: Vec<ResourceResult>,
+pub use config::*;
+pub use diagnostics::*;
+pub use report::*;
+pub use role_assignments::*;
+pub use artifacts::*;
+pub use progress::*;
+pub use runner::*;
+pub use subscription::*;
+pub use tags::*;
+pub use versions::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ResourceResult {
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    #[serde(rename = "name", skip_serializing_if = "Option::is_none")]
+    pub resource_name: Option<String>,
+    /// The ARM resource ID, when the plan reveals it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The ID of the resource this is a child or extension of, or of the
+    /// scope it is deployed to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// The full Terraform address, e.g.
+    /// `module.network.azurerm_subnet.this["app"]`.  On an expected result
+    /// it may also name a module or a resource without its instance key,
+    /// and matches one resource inside it, so a resource with several
+    /// instances needs an expected result for each.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    /// The API version an `azapi_resource` pins in its `type`.
+    #[serde(rename = "apiVersion", skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_expected: Option<bool>,
+    #[serde(rename = "tagDiscrepancies", default, skip_serializing_if = "Vec::is_empty")]
+    pub tag_discrepancies: Vec<TagDiscrepancy>,
+    /// The tags a tool plans, or `None` when it does not know them.
+    #[serde(skip)]
+    pub tags: Option<BTreeMap<String, String>>,
+    /// The tag keys Terraform plans with values only known after apply.
+    #[serde(skip)]
+    pub unknown_tags: Vec<String>,
 }
 
-impl Default for ApplicationConfig {
-    fn default() -> Self {
-        ApplicationConfig {
-            log_level: Some("info".to_string()),
-            infra_parameters: Vec::new(),
-            terraform_path: Some("./infra/terraform".to_string()),
-            bicep_path: Some("./infra/bicep".to_string()),
-            expected_results: Vec::new(),
+impl ResourceResult {
+    /// True when this resource satisfies `expected`: the same type and, if
+    /// both have a Terraform address, an address within the expected one.
+    /// Bicep resources have no address and match on type alone.
+    pub fn matches(&self, expected: &ResourceResult) -> bool {
+        self.resource_type == expected.resource_type
+            && match (&self.address, &expected.address) {
+                (Some(address), Some(expected)) => is_within_address(address, expected),
+                _ => true,
+            }
+    }
 
+    /// How surely this and `other` are the same resource: 2 for the same
+    /// resource ID, 1 for the same parent when either ID is unknown, such as
+    /// a Terraform role assignment named only after apply, and 0 otherwise.
+    pub fn get_affinity(&self, other: &ResourceResult) -> u8 {
+        let same = |x: &Option<String>, y: &Option<String>| match (x, y) {
+            (Some(x), Some(y)) => x.eq_ignore_ascii_case(y),
+            _ => false,
+        };
+        if self.id.is_some() && other.id.is_some() {
+            if same(&self.id, &other.id) {
+                2
+            } else {
+                0
+            }
+        } else if same(&self.parent, &other.parent) {
+            1
+        } else {
+            0
         }
     }
-}
-
-impl ApplicationConfig {
-    pub fn load(path: &Path) -> Result<ApplicationConfig> {
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
 
-        match serde_yaml::from_str(&contents) {
-            Ok(s) => Ok(s),
-            Err(e) => {
-                error!("Error parsing YAML {}", e);
-                Ok(ApplicationConfig::default())
-            }
+    /// True when both resource IDs are known and differ, so the two cannot
+    /// be the same resource whatever their type.
+    pub fn has_different_id(&self, other: &ResourceResult) -> bool {
+        match (&self.id, &other.id) {
+            (Some(x), Some(y)) => !x.eq_ignore_ascii_case(y),
+            _ => false,
         }
     }
 }
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
-pub struct InfraParameters {
-    #[serde(rename = "bicepName")]
-    pub bicep_name: Option<String>,
-    #[serde(rename = "terraformName")]
-    pub terraform_name: Option<String>,
-    pub value: String,
+
+/// True when `address` is `expected`, one of its `count`/`for_each`
+/// instances, or a resource inside the module it names.
+fn is_within_address(address: &str, expected: &str) -> bool {
+    address
+        .strip_prefix(expected)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
 }
 
+/// The Terraform resources of one module, keyed in the report by module
+/// path with `root` for the root module.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
-pub struct ResourceResult {
-    #[serde(rename = "type")]
-    pub resource_type: String,
-    #[serde(rename = "name", skip_serializing_if = "Option::is_none")]
-    pub resource_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub provider: Option<Provider>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub is_expected: Option<bool>,
+pub struct ModuleResults {
+    /// Expected resources that every compared tool plans.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matched: Vec<String>,
+    /// Resources that a compared tool does not plan.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<String>,
+    /// Resources that every compared tool plans but are not expected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unexpected: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct Provider {
     bicep: bool,
     terraform: bool,
@@ -94,58 +170,410 @@ impl Provider {
         self.terraform = true;
         self
     }
+    pub fn is_matched(&self) -> bool {
+        self.bicep && self.terraform
+    }
+    /// True when this provider includes every tool set on `other`.
+    pub fn covers(&self, other: &Provider) -> bool {
+        (self.bicep || !other.bicep) && (self.terraform || !other.terraform)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct ActualResults {
+    /// The tools that produced output for this comparison.
+    pub compared: Provider,
     pub expected_results: Vec<ResourceResult>,
     pub actual_results: Vec<ResourceResult>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modules: BTreeMap<String, ModuleResults>,
+    /// Terraform addresses left out of the comparison by `resourceClasses`,
+    /// by a `typeMappings` exclusion, or because the cloud cannot compare
+    /// their type, such as Microsoft Graph resources in a sovereign cloud.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<SubscriptionCheck>,
+    #[serde(rename = "roleAssignments", skip_serializing_if = "Option::is_none")]
+    pub role_assignments: Option<RoleAssignmentCheck>,
+}
+
+impl ActualResults {
+    /// True when any resource is missing from a tool that was compared or
+    /// has tag discrepancies, the tools planned against different
+    /// subscriptions, or they grant different roles.
+    pub fn has_failures(&self) -> bool {
+        let missing_resource = self.actual_results.iter().any(|x| {
+            !x.provider
+                .as_ref()
+                .is_some_and(|p| p.covers(&self.compared))
+        });
+        let tag_discrepancy = self.actual_results.iter().any(|x| !x.tag_discrepancies.is_empty());
+        let subscription_mismatch = self
+            .subscription
+            .as_ref()
+            .is_some_and(|x| !x.consistent);
+        let role_assignment_mismatch = self
+            .role_assignments
+            .as_ref()
+            .is_some_and(|x| !x.is_consistent());
+        missing_resource || tag_discrepancy || subscription_mismatch || role_assignment_mismatch
+    }
+
+    fn group_by_module(&mut self) {
+        for result in &self.actual_results {
+            let Some(address) = &result.address else {
+                continue;
+            };
+            let module = self
+                .modules
+                .entry(result.module.clone().unwrap_or_else(|| "root".to_string()))
+                .or_default();
+            if !result.provider.as_ref().is_some_and(|p| p.covers(&self.compared)) {
+                module.unmatched.push(address.clone());
+            } else if result.is_expected == Some(true) {
+                module.matched.push(address.clone());
+            } else {
+                module.unexpected.push(address.clone());
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct OutputTester {
     config: ApplicationConfig,
+    suite: SuiteConfig,
+    run_name: String,
+    stages: Stages,
+    artifacts_directory: Option<PathBuf>,
     azure_cli_authenticated: bool,
+    account: Option<AzAccountInfo>,
+    tool_versions: BTreeMap<String, String>,
     location: String,
     bicep_deployment_parameters: Vec<String>,
     terraform_deployment_parameters: Vec<String>,
     bicep_whatif_output: Option<AzureResourceChange>,
     terraform_plan_output: Option<TerraformResourceChange>,
+    terraform_structured_plan: Option<Value>,
+    started_at: Option<String>,
+    commands: Vec<CommandRecord>,
+    command_options: CommandOptions,
+    terraform_environment: Vec<(String, String)>,
+    terraform_cli: Option<TerraformCli>,
+    version_errors: Vec<String>,
+    bicep_diagnostics: Vec<Diagnostic>,
 }
 
 impl OutputTester {
     pub fn new() -> Self {
         OutputTester {
             config: ApplicationConfig::default(),
+            suite: ApplicationConfig::default().base_suite(),
+            run_name: DEFAULT_SUITE_NAME.to_string(),
+            stages: Stages::default(),
+            artifacts_directory: None,
             azure_cli_authenticated: false,
+            account: None,
+            tool_versions: BTreeMap::new(),
             location: "eastus".to_string(),
             bicep_deployment_parameters: Vec::new(),
             terraform_deployment_parameters: Vec::new(),
             bicep_whatif_output: None,
             terraform_plan_output: None,
+            terraform_structured_plan: None,
+            started_at: None,
+            commands: Vec::new(),
+            command_options: CommandOptions::default(),
+            terraform_environment: Vec::new(),
+            terraform_cli: None,
+            version_errors: Vec::new(),
+            bicep_diagnostics: Vec::new(),
         }
     }
 
     pub fn set_application_config(&mut self, config: ApplicationConfig) -> &mut Self {
         info!("Setting application config");
+        self.suite = config.base_suite();
         self.config = config;
         self
     }
 
+    pub fn set_suite_run(&mut self, run: &SuiteRun) -> &mut Self {
+        info!("Setting suite run {}", run.get_name());
+        self.suite = run.suite.clone();
+        self.run_name = run.get_name();
+        self.started_at = Some(format_timestamp(SystemTime::now()));
+        if let Some(artifacts_directory) = &self.artifacts_directory {
+            self.artifacts_directory = Some(artifacts_directory.join(run.get_directory_name()));
+        }
+        if self.stages.terraform && !self.stages.from_cache {
+            self.set_terraform_cli();
+        }
+        self
+    }
+
+    /// Finds the suite's Terraform compatible CLI and records its version.
+    fn set_terraform_cli(&mut self) {
+        let prefix = self.get_log_prefix("terraform");
+        self.terraform_cli = match TerraformCli::find(self.suite.terraform_binary.as_deref()) {
+            Ok(cli) => Some(cli),
+            Err(e) => {
+                error!("{} {}", prefix, e);
+                return;
+            }
+        };
+        let Some(cli) = &self.terraform_cli else {
+            return;
+        };
+        match cli.get_version() {
+            Ok(version) => {
+                self.tool_versions.insert(cli.get_name().to_string(), version);
+            }
+            Err(e) => warn!("{} Unable to determine the {} version: {}", prefix, cli.get_name(), e),
+        }
+    }
+
+    pub fn set_stages(&mut self, stages: Stages) -> &mut Self {
+        debug!("Stages: {:?}", stages);
+        self.stages = stages;
+        self
+    }
+
+    /// Raw tool output, logs, the effective config, the report and a run
+    /// manifest are written under this directory, one subdirectory per suite
+    /// run.  Comparing from cached outputs reads them back from here.
+    pub fn set_artifacts_directory(&mut self, artifacts_directory: Option<PathBuf>) -> &mut Self {
+        self.artifacts_directory = artifacts_directory;
+        self
+    }
+
+    pub fn set_command_options(&mut self, command_options: CommandOptions) -> &mut Self {
+        debug!("Command options: {:?}", command_options);
+        self.command_options = command_options;
+        self
+    }
+
     pub fn authenticate_azure_cli(&mut self) -> &mut Self {
+        // The provider needs to know the cloud even when the sign in is
+        // handled outside of this tool.
+        self.terraform_environment = vec![(
+            "ARM_ENVIRONMENT".to_string(),
+            self.config.cloud.get_terraform_environment().to_string(),
+        )];
+        // Pin the provider to the subscription what-if is given, so both
+        // tools plan against it even when signing in is skipped.
+        if let Some(subscription_id) = self.config.subscription_id.clone() {
+            self.set_terraform_variable("ARM_SUBSCRIPTION_ID", subscription_id);
+        }
+        if let Some(tenant_id) = self.config.tenant_id.clone() {
+            self.set_terraform_variable("ARM_TENANT_ID", tenant_id);
+        }
+        if !self.stages.authenticate {
+            info!("Skipping Azure CLI authentication.");
+            self.azure_cli_authenticated = true;
+            return self;
+        }
         info!("Authenticating Azure CLI.");
-        match set_azure_environment(None) {
-            Ok(_) => self.azure_cli_authenticated = true,
-            Err(e) => error!("Error setting Azure environment: {}", e),
+        let mut authentication = self
+            .config
+            .authentication
+            .clone()
+            .unwrap_or_default()
+            .resolve();
+        if authentication.tenant_id.is_none() {
+            authentication.tenant_id = self.config.tenant_id.clone();
+        }
+        let target_subscription = self
+            .config
+            .subscription_id
+            .as_deref()
+            .or(self.config.subscription_name.as_deref());
+        match set_azure_environment(target_subscription, &authentication, self.config.cloud) {
+            Ok(account) => {
+                if let Err(e) = self.check_account(&account) {
+                    error!("Error setting Azure environment: {}", e);
+                    return self;
+                }
+                self.azure_cli_authenticated = true;
+                // Every Terraform command gets these, so the azurerm provider
+                // signs in with the same identity and is pinned to the
+                // subscription the Azure CLI is using.
+                for (name, value) in authentication.get_terraform_environment() {
+                    self.set_terraform_variable(&name, value);
+                }
+                if let Some(subscription_id) = account.subscription_id.clone() {
+                    self.set_terraform_variable("ARM_SUBSCRIPTION_ID", subscription_id);
+                }
+                if let Some(tenant_id) = account.tenant_id.clone() {
+                    self.set_terraform_variable("ARM_TENANT_ID", tenant_id);
+                }
+                self.account = Some(account);
+            }
+            Err(e) => error!("Error setting Azure environment: {:#}", e),
         }
         debug!("Azure CLI authenticated: {}", self.azure_cli_authenticated);
         self
     }
 
+    /// Sets an environment variable for every Terraform command, replacing
+    /// any earlier value.
+    fn set_terraform_variable(&mut self, name: &str, value: String) {
+        self.terraform_environment.retain(|(x, _)| x != name);
+        self.terraform_environment.push((name.to_string(), value));
+    }
+
+    fn check_account(&self, account: &AzAccountInfo) -> Result<()> {
+        let matches = |expected: &Option<String>, actual: &Option<String>| match (expected, actual) {
+            (Some(expected), Some(actual)) => expected.eq_ignore_ascii_case(actual),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        if !matches(&self.config.subscription_id, &account.subscription_id)
+            || !matches(&self.config.subscription_name, &account.subscription_name)
+        {
+            return Err(anyhow!(
+                "The Azure CLI is using subscription {:?} rather than the configured one.",
+                account.subscription_id
+            ));
+        }
+        let cloud_name = Some(self.config.cloud.get_name().to_string());
+        if account.cloud_name.is_some() && !matches(&cloud_name, &account.cloud_name) {
+            return Err(anyhow!(
+                "The Azure CLI is signed in to cloud {:?} rather than {}.",
+                account.cloud_name,
+                self.config.cloud.get_name()
+            ));
+        }
+        if !matches(&self.config.tenant_id, &account.tenant_id) {
+            return Err(anyhow!(
+                "The Azure CLI is signed in to tenant {:?} rather than the configured one.",
+                account.tenant_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// The subscription both tools are expected to plan against.
+    fn get_subscription_id(&self) -> Option<&str> {
+        self.account
+            .as_ref()
+            .and_then(|x| x.subscription_id.as_deref())
+            .or(self.config.subscription_id.as_deref())
+    }
+
+    /// Records the versions of the tools the selected stages will call.
+    pub fn collect_tool_versions(&mut self) -> &mut Self {
+        if self.stages.from_cache {
+            return self;
+        }
+        if self.stages.bicep {
+            match get_az_cli_version() {
+                Ok(version) => {
+                    self.tool_versions.insert("az".to_string(), version);
+                }
+                Err(e) => warn!("Unable to determine the Azure CLI version: {}", e),
+            }
+            match get_bicep_version() {
+                Ok(version) => {
+                    self.tool_versions.insert("bicep".to_string(), version);
+                }
+                Err(e) => warn!("Unable to determine the Bicep CLI version: {:#}", e),
+            }
+        }
+        debug!("Tool versions: {:?}", self.tool_versions);
+        self
+    }
+
+    /// Records the provider versions from the suite's dependency lock file
+    /// and checks every tool the run uses against `requiredVersions`.  A
+    /// run that does not meet them is not started.
+    pub fn check_tool_versions(&mut self) -> &mut Self {
+        if self.stages.from_cache {
+            return self;
+        }
+        if self.stages.terraform {
+            let path = PathBuf::from(self.suite.terraform_path.clone().unwrap_or_default());
+            match read_provider_versions(&path) {
+                Ok(providers) => self.tool_versions.extend(providers),
+                Err(e) => debug!("No provider versions from {}: {}", path.join(LOCK_FILE).display(), e),
+            }
+        }
+
+        let terraform_name = self.terraform_cli.as_ref().map(|x| x.get_name());
+        for (tool, constraint) in &self.config.required_versions {
+            let used = match tool.as_str() {
+                "az" | "bicep" => self.stages.bicep,
+                "terraform" | "tofu" => self.stages.terraform && terraform_name == Some(tool.as_str()),
+                _ => self.stages.terraform,
+            };
+            if !used {
+                continue;
+            }
+            let result = match find_version(&self.tool_versions, tool) {
+                Some(version) => constraint.check(tool, version),
+                None => Err(anyhow!("The {} version could not be determined.", tool)),
+            };
+            if let Err(e) = result {
+                self.version_errors.push(e.to_string());
+            }
+        }
+        for e in &self.version_errors {
+            error!("{} {}", self.get_log_prefix("versions"), e);
+        }
+        self
+    }
+
+    pub fn get_config(&self) -> &ApplicationConfig {
+        &self.config
+    }
+
+    pub fn get_stages(&self) -> &Stages {
+        &self.stages
+    }
+
+    pub fn get_drift(&self) -> Vec<DriftedResource> {
+        self.terraform_plan_output
+            .iter()
+            .flat_map(|x| x.resource_drift.iter())
+            .filter_map(|x| x.change.as_ref())
+            .map(|x| DriftedResource {
+                address: x.resource.get_address().to_string(),
+                resource_type: x.resource.get_resource_type().to_string(),
+                action: x.action.clone(),
+            })
+            .collect()
+    }
+
+    pub fn get_tool_versions(&self) -> &BTreeMap<String, String> {
+        &self.tool_versions
+    }
+
+    /// Bicep build diagnostics followed by Terraform's.
+    pub fn get_diagnostics(&self) -> Vec<Diagnostic> {
+        let terraform_diagnostics = self
+            .terraform_plan_output
+            .iter()
+            .flat_map(|x| x.diagnostics.iter())
+            .filter_map(|x| x.diagnostic.as_ref())
+            .map(Diagnostic::from);
+        let malformed_lines = self
+            .terraform_plan_output
+            .iter()
+            .flat_map(|x| x.malformed_lines.iter())
+            .map(Diagnostic::from);
+        self.bicep_diagnostics
+            .iter()
+            .cloned()
+            .chain(terraform_diagnostics)
+            .chain(malformed_lines)
+            .collect()
+    }
+
     pub fn set_deployment_parameters(&mut self) -> &mut Self {
         info!("Setting deployment parameters.");
-        debug!("Deployment parameters: {:?}", self.config.infra_parameters);
-        for entry in &self.config.infra_parameters {
+        debug!("Deployment parameters: {:?}", self.suite.infra_parameters);
+        for entry in &self.suite.infra_parameters {
             if let Some(bicep_name) = &entry.bicep_name {
                 if bicep_name == "location" {
                     self.location = entry.value.to_owned();
@@ -161,112 +589,441 @@ impl OutputTester {
         self
     }
 
-    pub fn execute_bicep_whatif(&mut self) -> &mut Self {
-        info!("Executing Bicep WhatIf.");
-        if !self.azure_cli_authenticated {
-            error!("Azure CLI not authenticated. Skipping Bicep What If.");
+    /// Runs the Bicep what-if on one thread and `terraform init` followed by
+    /// `terraform plan` on another, since neither tool depends on the other.
+    /// Only the selected stages run; with `from_cache` set, the outputs of
+    /// an earlier run are loaded from the artifacts directory instead.
+    pub fn execute_bicep_whatif_and_terraform_plan(&mut self) -> &mut Self {
+        if self.stages.from_cache {
+            self.load_cached_outputs();
+            return self;
+        }
+        if !self.version_errors.is_empty() {
+            error!("{} Tool versions do not meet the requirements. Skipping both tools.", self.get_log_prefix("versions"));
             return self;
         }
-        // Execute the bicep whatif command and store the output in self.bicep_whatif_output
-        let mut command_arguments = vec![
-            "sub",
-            "what-if",
-            "--location",
-            &self.location,
+        let tester = &*self;
+        let (bicep, terraform) = thread::scope(|s| {
+            let bicep = s.spawn(move || {
+                let mut commands = Vec::new();
+                let mut diagnostics = Vec::new();
+                if !tester.stages.bicep {
+                    return (None, diagnostics, commands);
+                }
+                let whatif = tester.get_bicep_whatif(&mut commands, &mut diagnostics);
+                (whatif, diagnostics, commands)
+            });
+            let terraform = s.spawn(move || {
+                let mut commands = Vec::new();
+                if !tester.stages.terraform {
+                    return (None, commands);
+                }
+                tester.run_terraform_init(&mut commands);
+                let plan = tester.get_terraform_plan(&mut commands);
+                (plan, commands)
+            });
+            (
+                bicep.join().unwrap_or_else(|_| {
+                    error!("{} Bicep WhatIf thread panicked.", tester.get_log_prefix("bicep"));
+                    (None, Vec::new(), Vec::new())
+                }),
+                terraform.join().unwrap_or_else(|_| {
+                    error!("{} Terraform Plan thread panicked.", tester.get_log_prefix("terraform"));
+                    (None, Vec::new())
+                }),
+            )
+        });
+        self.bicep_whatif_output = bicep.0;
+        self.bicep_diagnostics = bicep.1;
+        self.commands.extend(bicep.2);
+        if let Some((plan, structured_plan)) = terraform.0 {
+            self.check_plan_version(&plan);
+            self.terraform_plan_output = Some(plan);
+            self.terraform_structured_plan = structured_plan;
+        }
+        self.commands.extend(terraform.1);
+        self
+    }
+
+    /// The plan's `version` event names the CLI version that actually ran,
+    /// which can differ from `version -json` when a wrapper picks the binary.
+    fn check_plan_version(&mut self, plan: &TerraformResourceChange) {
+        let (Some(cli), Some(plan_version)) = (
+            &self.terraform_cli,
+            plan.version.as_ref().and_then(|x| x.terraform.clone()),
+        ) else {
+            return;
+        };
+        match self.tool_versions.get(cli.get_name()) {
+            Some(version) if *version != plan_version => warn!(
+                "{} {} reported version {} but the plan ran with {}.",
+                self.get_log_prefix("terraform"),
+                cli.get_name(),
+                version,
+                plan_version
+            ),
+            Some(_) => {}
+            None => {
+                self.tool_versions.insert(cli.get_name().to_string(), plan_version);
+            }
+        }
+    }
+
+    /// Writes the effective suite config, the report and the run manifest
+    /// to the artifacts directory, if there is one.
+    pub fn write_run_artifacts(&self, report: &SuiteReport) {
+        let Some(artifacts_directory) = &self.artifacts_directory else {
+            return;
+        };
+        if self.stages.from_cache {
+            // Keep the manifest of the run that produced the cached outputs.
+            write_json_artifact(artifacts_directory, REPORT_FILE, report);
+            return;
+        }
+        let manifest = RunManifest {
+            run: self.run_name.clone(),
+            started_at: self.started_at.clone().unwrap_or_default(),
+            finished_at: format_timestamp(SystemTime::now()),
+            tool_versions: self.tool_versions.clone(),
+            subscription: self.account.clone(),
+            commands: self.commands.clone(),
+        };
+        write_yaml_artifact(artifacts_directory, CONFIG_FILE, &self.suite);
+        write_json_artifact(artifacts_directory, REPORT_FILE, report);
+        write_json_artifact(artifacts_directory, MANIFEST_FILE, &manifest);
+    }
+
+    fn get_log_prefix(&self, tool: &str) -> String {
+        format!("[{} {}]", self.run_name, tool)
+    }
+
+    fn load_cached_outputs(&mut self) {
+        let Some(artifacts_directory) = self.artifacts_directory.clone() else {
+            error!("No artifacts directory set. Nothing to compare.");
+            return;
+        };
+        info!("Loading cached outputs from {}.", artifacts_directory.display());
+        if self.stages.bicep {
+            if let Some(stderr) = read_cached_output(&artifacts_directory, BICEP_WHATIF_STDERR_FILE) {
+                self.bicep_diagnostics = parse_bicep_diagnostics(&stderr);
+            }
+            match read_cached_output(&artifacts_directory, BICEP_WHATIF_FILE) {
+                Some(output) => match serde_json::from_str(&output) {
+                    Ok(whatif) => self.bicep_whatif_output = Some(whatif),
+                    Err(e) => error!("Failed to parse cached Bicep WhatIf output: {}", e),
+                },
+                None => warn!("No cached Bicep WhatIf output found."),
+            }
+        }
+        if self.stages.terraform {
+            match read_cached_output(&artifacts_directory, TERRAFORM_PLAN_FILE) {
+                Some(output) => {
+                    self.terraform_plan_output = Some(TerraformPlanParser::parse(&output));
+                }
+                None => warn!("No cached Terraform Plan output found."),
+            }
+            if let Some(output) = read_cached_output(&artifacts_directory, TERRAFORM_STRUCTURED_PLAN_FILE) {
+                match serde_json::from_str(&output) {
+                    Ok(plan) => self.terraform_structured_plan = Some(plan),
+                    Err(e) => error!("Failed to parse cached Terraform structured plan: {}", e),
+                }
+            }
+        }
+    }
+
+    fn write_artifact(&self, file_name: &str, contents: &str) {
+        if let Some(artifacts_directory) = &self.artifacts_directory {
+            write_artifact(artifacts_directory, file_name, contents);
+        }
+    }
+
+    /// Runs `command`, recording it for the manifest and saving its stderr
+    /// as `<artifact_name>.stderr.log`.  Returns `None` when the process
+    /// could not be started.
+    fn run_command<'a>(
+        &self,
+        command: Command<'a>,
+        artifact_name: &str,
+        commands: &mut Vec<CommandRecord>,
+    ) -> Option<Command<'a>> {
+        let display_name = command.get_display_name();
+        let started_at = SystemTime::now();
+        let command = command
+            .with_verbose(self.command_options.verbose)
+            .with_show_progress(self.command_options.show_progress, &self.get_log_prefix(artifact_name))
+            .with_timeout(self.command_options.timeout);
+        match command.run() {
+            Ok(command) => {
+                commands.push(CommandRecord::new(display_name, started_at, command.get_exit_code()));
+                if let Some(stderr) = command.get_stderr() {
+                    self.write_artifact(&format!("{}.stderr.log", artifact_name), &stderr);
+                }
+                Some(command)
+            }
+            Err(e) => {
+                error!("{} Failed to execute {}: {}", self.get_log_prefix(artifact_name), display_name, e);
+                commands.push(CommandRecord::new(display_name, started_at, None));
+                None
+            }
+        }
+    }
+
+    fn get_bicep_whatif(
+        &self,
+        commands: &mut Vec<CommandRecord>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<AzureResourceChange> {
+        let prefix = self.get_log_prefix("bicep");
+        info!("{} Executing Bicep WhatIf.", prefix);
+        if !self.azure_cli_authenticated {
+            error!("{} Azure CLI not authenticated. Skipping Bicep What If.", prefix);
+            return None;
+        }
+        let scope_arguments = self
+            .suite
+            .scope
+            .clone()
+            .unwrap_or_default()
+            .get_whatif_arguments(&self.location);
+        let mut command_arguments: Vec<&str> = scope_arguments.iter().map(|x| x.as_str()).collect();
+        command_arguments.extend([
             "--template-file",
             "main.bicep",
             "--no-pretty-print",
             "--output",
             "json",
-        ];
+        ]);
+        if let Some(subscription_id) = self.get_subscription_id() {
+            command_arguments.extend(["--subscription", subscription_id]);
+        }
 
         if self.bicep_deployment_parameters.is_empty() {
-            error!("No Bicep deployment parameters.");
+            error!("{} No Bicep deployment parameters.", prefix);
         }
         else {
             command_arguments.push("--parameters");
             for parameter in &self.bicep_deployment_parameters {
-                debug!("Bicep WhatIf parameter: {}", parameter);
+                debug!("{} Bicep WhatIf parameter: {}", prefix, parameter);
                 command_arguments.push(parameter);
             }
         }
 
-        let path = PathBuf::from(self.config.bicep_path.as_ref().unwrap());
-        let az_bicep = get_az_cli_command("deployment")
+        let path = PathBuf::from(self.suite.bicep_path.as_ref().unwrap());
+        let mut command = match get_az_cli_command("deployment") {
+            Ok(command) => command,
+            Err(e) => {
+                error!("{} {:#}", prefix, e);
+                return None;
+            }
+        };
+        command = command
             .with_args(command_arguments)
-            .with_working_directory(&path)
-            .run()
-            .expect("Failed to execute Bicep WhatIf command");
-        if let Some(output) = az_bicep.get_stdout() {
-            self.bicep_whatif_output = serde_json::from_str(&output).unwrap();
-            debug!("Bicep WhatIf output: {:#?}", self.bicep_whatif_output);
+            .with_working_directory(&path);
+        if self.command_options.show_progress {
+            // The what-if result only arrives at the end, but Bicep build
+            // output shows up on stderr while ARM is still working.
+            let stderr_prefix = prefix.clone();
+            command = command.with_stderr_handler(LineHandler::new(move |line| {
+                if !line.trim().is_empty() {
+                    debug!("{} {}", stderr_prefix, line);
+                }
+            }));
+        }
+        let az_bicep = self.run_command(command, "bicep-whatif", commands)?;
+        diagnostics.extend(parse_bicep_diagnostics(&az_bicep.get_stderr().unwrap_or_default()));
+        let output = az_bicep.get_stdout()?;
+        self.write_artifact(BICEP_WHATIF_FILE, &output);
+        match serde_json::from_str(&output) {
+            Ok(whatif) => {
+                debug!("{} Bicep WhatIf output: {:#?}", prefix, whatif);
+                Some(whatif)
+            }
+            Err(e) => {
+                error!("{} Failed to parse Bicep WhatIf output: {}", prefix, e);
+                None
+            }
         }
-        self
     }
 
-    pub fn init_terraform_environment(&mut self) -> &mut Self {
-        info!("Initializing Terraform environment.");
+    fn run_terraform_init(&self, commands: &mut Vec<CommandRecord>) {
+        let prefix = self.get_log_prefix("terraform");
+        if !self.stages.terraform_init {
+            info!("{} Skipping Terraform Init.", prefix);
+            return;
+        }
+        info!("{} Initializing Terraform environment.", prefix);
         if !self.azure_cli_authenticated {
-            error!("Azure CLI not authenticated. Skipping Terraform Init.");
-            return self;
+            error!("{} Azure CLI not authenticated. Skipping Terraform Init.", prefix);
+            return;
         }
-        let path = PathBuf::from(self.config.terraform_path.as_ref().unwrap());
-        get_terraform_command("init")
+        let Some(cli) = &self.terraform_cli else {
+            error!("{} No Terraform CLI. Skipping Terraform Init.", prefix);
+            return;
+        };
+        let path = PathBuf::from(self.suite.terraform_path.as_ref().unwrap());
+        let command = cli
+            .get_command("init")
             .with_working_directory(&path)
-            .run()
-            .expect("Failed to execute Terraform Init command");
-        self
+            .with_env(self.terraform_environment.clone())
+            .with_args(vec!["-input=false"]);
+        self.run_command(command, "terraform-init", commands);
     }
 
-    pub fn execute_terraform_plan(&mut self) -> &mut Self {
-        info!("Executing Terraform Plan.");
+    /// Runs `terraform plan`, keeping both the JSON event stream and the
+    /// structured plan that `terraform show -json` renders from the saved
+    /// plan file.
+    fn get_terraform_plan(
+        &self,
+        commands: &mut Vec<CommandRecord>,
+    ) -> Option<(TerraformResourceChange, Option<Value>)> {
+        let prefix = self.get_log_prefix("terraform");
+        info!("{} Executing Terraform Plan.", prefix);
         if !self.azure_cli_authenticated {
-            error!("Azure CLI not authenticated. Skipping Terraform Plan.");
-            return self;
+            error!("{} Azure CLI not authenticated. Skipping Terraform Plan.", prefix);
+            return None;
         }
-        
-        let path = PathBuf::from(self.config.terraform_path.as_ref().unwrap());
 
-        let mut command_arguments = vec!["-json"];
+        let Some(cli) = &self.terraform_cli else {
+            error!("{} No Terraform CLI. Skipping Terraform Plan.", prefix);
+            return None;
+        };
+        let path = PathBuf::from(self.suite.terraform_path.as_ref().unwrap());
+        let plan_file = env::temp_dir().join(format!(
+            "infra-test-{}-{}.tfplan",
+            process::id(),
+            self.run_name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        ));
+        let plan_file_argument = format!("-out={}", plan_file.display());
+
+        let mut command_arguments = vec!["-json", "-input=false", &plan_file_argument];
+        if self.stages.refresh_only {
+            command_arguments.push("-refresh-only");
+        }
         for parameter in &self.terraform_deployment_parameters {
-            debug!("Terraform Plan parameter: {}", parameter);
+            debug!("{} Terraform Plan parameter: {}", prefix, parameter);
             command_arguments.push("-var");
             command_arguments.push(parameter);
         }
 
-        let command = get_terraform_command("plan")
+        let parser = Arc::new(Mutex::new(TerraformPlanParser::new()));
+        let progress = self
+            .command_options
+            .show_progress
+            .then(|| TerraformProgress::new(&prefix));
+        let handler_parser = Arc::clone(&parser);
+        let command = cli
+            .get_command("plan")
             .with_working_directory(&path)
+            .with_env(self.terraform_environment.clone())
             .with_args(command_arguments)
-            .run()
-            .expect("Failed to execute Terraform Plan command");
+            .with_stdout_handler(LineHandler::new(move |line| {
+                if let Some(progress) = &progress {
+                    progress.handle_line(line);
+                }
+                handler_parser.lock().unwrap().push_line(line);
+            }));
+        let command = self.run_command(command, "terraform-plan", commands)?;
 
-        if let Some(output) = command.get_stdout() {
-            let result = self.convert_to_terraform_plan(&output);
-            self.terraform_plan_output = Some(result);
-            debug!("Terraform Plan output: {:#?}", self.terraform_plan_output);
+        let output = command.get_stdout()?;
+        self.write_artifact(TERRAFORM_PLAN_FILE, &output);
+        let result = std::mem::take(&mut *parser.lock().unwrap()).finish();
+        for line in &result.malformed_lines {
+            warn!(
+                "{} Terraform Plan output line {} is not a JSON event: {}",
+                prefix, line.line_number, line.error
+            );
         }
-        
-        self
+        debug!("{} Terraform Plan output: {:#?}", prefix, result);
+
+        let structured_plan = if command.success() {
+            self.get_terraform_structured_plan(cli, &path, &plan_file, commands)
+        } else {
+            None
+        };
+        if let Err(e) = fs::remove_file(&plan_file) {
+            debug!("{} Unable to remove {}: {}", prefix, plan_file.display(), e);
+        }
+        Some((result, structured_plan))
     }
 
-    pub fn compare_bicep_whatif_and_terraform_plan(&self) -> Result<()> {
+    fn get_terraform_structured_plan(
+        &self,
+        cli: &TerraformCli,
+        path: &Path,
+        plan_file: &Path,
+        commands: &mut Vec<CommandRecord>,
+    ) -> Option<Value> {
+        let prefix = self.get_log_prefix("terraform");
+        let plan_file = plan_file.to_string_lossy();
+        let command = cli
+            .get_command("show")
+            .with_working_directory(path)
+            .with_env(self.terraform_environment.clone())
+            .with_args(vec!["-json", &plan_file]);
+        let command = self.run_command(command, "terraform-show", commands)?;
+        let output = command.get_stdout()?;
+        self.write_artifact(TERRAFORM_STRUCTURED_PLAN_FILE, &output);
+        match serde_json::from_str(&output) {
+            Ok(plan) => Some(plan),
+            Err(e) => {
+                warn!("{} Failed to parse the Terraform structured plan: {}", prefix, e);
+                None
+            }
+        }
+    }
+
+    pub fn compare_bicep_whatif_and_terraform_plan(&self) -> Result<ActualResults> {
         info!("Comparing Bicep WhatIf and Terraform Plan.");
-        if !self.azure_cli_authenticated {
-            error!("Azure CLI not authenticated. Skipping Comparison.");
-            return Err(anyhow!("Azure CLI not authenticated. Skipping Comparison."));
+        if !self.version_errors.is_empty() {
+            return Err(anyhow!(
+                "Tool version requirements not met: {}",
+                self.version_errors.join(" ")
+            ));
+        }
+        if self.stages.refresh_only {
+            info!("Refresh-only run. Skipping comparison.");
+            return Ok(ActualResults {
+                compared: Provider::new().set_terraform(),
+                ..Default::default()
+            });
         }
         // If the expected results are not set, then we can't compare
-        if self.config.expected_results.is_empty() {
+        if self.suite.expected_results.is_empty() {
             warn!("Expected results not set. Skipping comparison.");
-            return Ok(());
+            return Ok(ActualResults::default());
+        }
+
+        // A selected tool that failed breaks the run.  Only a cached run
+        // with no saved output for a tool compares the other tool alone.
+        let mut compared = Provider::new();
+        if self.bicep_whatif_output.is_some() {
+            compared = compared.set_bicep();
+        } else if self.stages.bicep && !self.stages.from_cache {
+            return Err(anyhow!("Bicep WhatIf was selected but produced no output."));
+        } else if self.stages.bicep {
+            warn!("No cached Bicep WhatIf output. Comparing Terraform Plan only.");
+        }
+        if self.terraform_plan_output.is_some() {
+            compared = compared.set_terraform();
+        } else if self.stages.terraform && !self.stages.from_cache {
+            return Err(anyhow!("Terraform Plan was selected but produced no output."));
+        } else if self.stages.terraform {
+            warn!("No cached Terraform Plan output. Comparing Bicep WhatIf only.");
+        }
+        if compared == Provider::new() {
+            return Err(anyhow!("Neither Bicep WhatIf nor Terraform Plan output to compare."));
         }
 
         // Compare the bicep whatif and terraform plan outputs
         let mut azure_resources = self.get_bicep_resources_for_comparison();
-        let mut terraform_resources = self.get_terraform_resources_for_comparison();
+        let (mut terraform_resources, excluded) = self.get_terraform_resources_for_comparison();
 
-        let mut response = ActualResults::default();
-        for expected in &self.config.expected_results {
+        let mut response = ActualResults {
+            compared,
+            excluded,
+            ..Default::default()
+        };
+        for expected in &self.suite.expected_results {
             let actual_result = self.process_expected_results(expected, &mut azure_resources, &mut terraform_resources);
             response.actual_results.push(actual_result);
         }
@@ -281,129 +1038,325 @@ impl OutputTester {
             response.actual_results.push(unexpected_result);
         });
 
-        response.expected_results = self.config.expected_results.clone();
-        println!("Results: {} ", serde_json::to_string_pretty(&response).unwrap() );
+        if response.compared.is_matched() || self.get_subscription_id().is_some() {
+            let check = SubscriptionCheck::new(
+                self.get_subscription_id(),
+                self.bicep_whatif_output.as_ref(),
+                self.terraform_structured_plan.as_ref(),
+            );
+            if !check.consistent {
+                error!("Bicep and Terraform did not plan against the same subscription: {:?}", check);
+            }
+            response.subscription = Some(check);
+        }
 
-        Ok(())
+        if response.compared.is_matched() {
+            let check = self.check_role_assignments();
+            if !check.is_consistent() {
+                error!(
+                    "Bicep and Terraform do not grant the same roles: {} missing from Terraform, {} extra",
+                    check.missing.len(),
+                    check.extra.len()
+                );
+            }
+            if !check.inconclusive.is_empty() {
+                warn!(
+                    "{} Terraform role assignments name a role that cannot be compared with Bicep's.",
+                    check.inconclusive.len()
+                );
+            }
+            response.role_assignments = Some(check);
+        }
+
+        response.expected_results = self.suite.expected_results.clone();
+        response.group_by_module();
+        debug!("Results: {:#?}", response);
+
+        Ok(response)
     }
 
     fn process_unexpected_bicep_results(&self,  terraform_resources: &mut Vec<ResourceResult>, x: &ResourceResult) -> ResourceResult {
         let mut unexpected_provider = Provider::new().set_bicep();
-        if terraform_resources.iter().any(|y| y.resource_type == x.resource_type){
+        let counterpart = take_counterpart(x, terraform_resources);
+        if counterpart.is_some() {
             unexpected_provider = unexpected_provider.set_terraform();
-            self.remove_matched_resource(&x.resource_type,  terraform_resources)
         }
-        let unexpected_result = ResourceResult {
+        ResourceResult {
             resource_type: x.resource_type.clone(),
             resource_name: None,
+            id: x.id.clone(),
+            parent: x.parent.clone(),
             provider: Some(unexpected_provider),
             is_expected: Some(false),
-        };
-        unexpected_result
+            tag_discrepancies: self.config.tag_policy.check(Some(x), counterpart.as_ref()),
+            ..Default::default()
+        }
     }
 
     fn process_unexpected_terraform_results(&self, azure_resources: &mut Vec<ResourceResult>, x: &ResourceResult) -> ResourceResult {
         let mut unexpected_provider = Provider::new().set_terraform();
-        if azure_resources.iter().any(|y| y.resource_type == x.resource_type) {
+        let counterpart = take_counterpart(x, azure_resources);
+        if counterpart.is_some() {
             unexpected_provider = unexpected_provider.set_bicep();
-            self.remove_matched_resource(&x.resource_type, azure_resources)
         }
-        let unexpected_result = ResourceResult {
+        ResourceResult {
             resource_type: x.resource_type.clone(),
             resource_name: None,
+            id: x.id.clone(),
+            parent: x.parent.clone(),
+            address: x.address.clone(),
+            module: x.module.clone(),
+            api_version: x.api_version.clone(),
             provider: Some(unexpected_provider),
             is_expected: Some(false),
-        };
-        unexpected_result
+            tag_discrepancies: self.config.tag_policy.check(counterpart.as_ref(), Some(x)),
+            tags: None,
+            unknown_tags: Vec::new(),
+        }
     }
 
     fn process_expected_results(&self, expected: &ResourceResult, azure_resources: &mut Vec<ResourceResult>, terraform_resources: &mut Vec<ResourceResult>) -> ResourceResult {
         let mut actual_result = expected.clone();
         actual_result.is_expected = Some(true);
         let mut provider = Provider::new();
-        if azure_resources.iter().any(|x| x.resource_type == expected.resource_type) {
+        let (azure_resource, terraform_resource) = take_matching_pair(expected, azure_resources, terraform_resources);
+        actual_result.tag_discrepancies = self
+            .config
+            .tag_policy
+            .check(azure_resource.as_ref(), terraform_resource.as_ref());
+        if let Some(azure_resource) = azure_resource {
             provider = provider.set_bicep();
-            self.remove_matched_resource(&expected.resource_type, azure_resources);
+            actual_result.id = azure_resource.id;
+            actual_result.parent = azure_resource.parent;
         }
-        if terraform_resources.iter().any(|x| x.resource_type == expected.resource_type) {
+        if let Some(terraform_resource) = terraform_resource {
             provider = provider.set_terraform();
-            self.remove_matched_resource(&expected.resource_type, terraform_resources);
+            actual_result.id = actual_result.id.or(terraform_resource.id);
+            actual_result.parent = actual_result.parent.or(terraform_resource.parent);
+            actual_result.address = terraform_resource.address;
+            actual_result.module = terraform_resource.module;
+            actual_result.api_version = terraform_resource.api_version;
         }
         actual_result.provider = Some(provider);
         actual_result
     }
 
-    fn get_terraform_resources_for_comparison(&self) -> Vec<ResourceResult> {
-        self.terraform_plan_output
-            .as_ref()
-            .unwrap()
-            .planned_change
+    /// The planned Terraform resources of the configured `resourceClasses`,
+    /// and the addresses of those left out.
+    fn get_included_terraform_resources(&self) -> (Vec<&TerraformResource>, Vec<String>) {
+        let mut excluded = Vec::new();
+        let changes = self
+            .terraform_plan_output
             .iter()
-            .map(|x| x.change.as_ref().unwrap().resource.get_comparison_resource())
-            .collect::<Vec<ResourceResult>>()
+            .flat_map(|x| x.planned_change.iter())
+            .filter_map(|x| x.change.as_ref())
+            .map(|x| &x.resource)
+            .filter(|x| {
+                let class = x.get_class();
+                let included = self.config.resource_classes.contains(&class);
+                if !included {
+                    debug!("Excluding {:?} resource {}", class, x.get_address());
+                    excluded.push(x.get_address().to_string());
+                }
+                included
+            })
+            .collect();
+        (changes, excluded)
     }
 
-    fn get_bicep_resources_for_comparison(&self) -> Vec<ResourceResult> {
-        self.bicep_whatif_output
-            .as_ref()
-            .unwrap()
-            .changes
+    /// The canonical resources for `changes`, keyed by Terraform address.
+    fn get_terraform_canonical_resources(&self, changes: &[&TerraformResource]) -> HashMap<String, CanonicalResource> {
+        let addresses: Vec<(&str, &str)> = changes
             .iter()
-            .map(|x| x.after.as_ref().unwrap().get_comparison_resource()).collect::<Vec<ResourceResult>>()
+            .map(|x| (x.get_address(), x.get_resource_type()))
+            .collect();
+        get_terraform_canonical_resources(
+            self.terraform_structured_plan.as_ref(),
+            &addresses,
+            self.get_subscription_id(),
+        )
     }
 
-    fn remove_matched_resource(&self, resource: &str, vec: &mut Vec<ResourceResult>) {
-        if let Some(index) = vec.iter().position(|value| *&value.resource_type == resource) {
-            vec.swap_remove(index);
-        }
-    }
+    /// The planned Terraform resources to compare, with the children they
+    /// declare inline, and the addresses left out by `resourceClasses`.
+    fn get_terraform_resources_for_comparison(&self) -> (Vec<ResourceResult>, Vec<String>) {
+        let types = ResourceTypeMap::new(self.config.cloud, &self.config.type_mappings);
+        let planned_values = self
+            .terraform_structured_plan
+            .as_ref()
+            .map(get_planned_values)
+            .unwrap_or_default();
+        let unknown_values = self
+            .terraform_structured_plan
+            .as_ref()
+            .map(get_unknown_values)
+            .unwrap_or_default();
+        let (changes, mut excluded) = self.get_included_terraform_resources();
+        let canonical = self.get_terraform_canonical_resources(&changes);
 
-    fn convert_to_terraform_plan(&self, output: &str) -> TerraformResourceChange {
-        let broken_output: Vec<&str> = output.split("\n").collect();
-        let mut result = TerraformResourceChange::default();
-        for entry in broken_output {
-            debug!("Terraform Plan output: {:?}", entry);
-            if str::is_empty(entry) {
+        let mut resources = Vec::new();
+        let mut children = Vec::new();
+        for change in changes {
+            let resource = &canonical[change.get_address()];
+            let arm_type = resource.id.as_ref().map(|x| x.get_resource_type());
+            let planned_values = planned_values.get(change.get_address());
+            let Some(mut result) = change.get_comparison_resource(planned_values, arm_type.as_deref(), &types) else {
+                debug!("Excluding {} by type mapping or cloud", change.get_address());
+                excluded.push(change.get_address().to_string());
                 continue;
+            };
+            result.id = resource.id.as_ref().map(|x| x.to_string());
+            result.parent = resource.parent.as_ref().map(|x| x.to_string());
+            // azurerm resources that can be tagged always plan a `tags` value.
+            result.tags = planned_values
+                .filter(|x| x.get("tags").is_some())
+                .map(|_| resource.tags.clone());
+            if let Some(unknown_tags) = unknown_values.get(change.get_address()).and_then(|x| x["tags"].as_object()) {
+                result.unknown_tags = unknown_tags
+                    .iter()
+                    .filter(|(_, unknown)| unknown.as_bool() == Some(true))
+                    .map(|(key, _)| key.clone())
+                    .collect();
             }
-            let temp: TerraformPlanStep = serde_json::from_str(entry).unwrap();
-            match temp.record_type.as_str() {
-                "version" => {
-                    debug!("Setting Terraform Plan Version");
-                    result.version = Some(temp);
-                }
-                "apply_start" => {
-                    debug!("Setting Terraform Plan Apply Start");
-                    result.apply_start.push(temp);
-                }
-                "apply_complete" => {
-                    debug!("Setting Terraform Plan Apply Complete");
-                    result.apply_complete.push(temp);
-                }
-                "planned_change" => {
-                    debug!("Setting Terraform Plan Planned Change");
-                    result.planned_change.push(temp);
-                }
-                "change_summary" => {
-                    debug!("Setting Terraform Plan Change Summary");
-                    result.change_summary = Some(temp);
-                }
-                "outputs" => {
-                    debug!("Setting Terraform Plan Outputs");
-                    result.outputs = Some(temp);
-                }
-                _ => {
-                    error!("Unknown Terraform Plan output: {:?}", temp);
+            for child in resource.get_inline_children() {
+                if let Some(mut child) = child.get_comparison_resource(&types) {
+                    child.address = result.address.clone();
+                    child.module = result.module.clone();
+                    children.push(child);
                 }
             }
+            resources.push(result);
+        }
+        add_inline_children(&mut resources, children);
+        (resources, excluded)
+    }
+
+    /// The resources the Bicep WhatIf deploys, with the children they
+    /// declare inline.
+    fn get_bicep_resources_for_comparison(&self) -> Vec<ResourceResult> {
+        let types = ResourceTypeMap::new(self.config.cloud, &self.config.type_mappings);
+        let mut resources = Vec::new();
+        let mut children = Vec::new();
+        for resource in self
+            .bicep_whatif_output
+            .iter()
+            .flat_map(|x| x.changes.iter())
+            .filter_map(|x| x.get_canonical_resource())
+        {
+            children.extend(
+                resource
+                    .get_inline_children()
+                    .iter()
+                    .filter_map(|x| x.get_comparison_resource(&types)),
+            );
+            resources.extend(resource.get_comparison_resource(&types).map(|mut x| {
+                x.tags = Some(resource.tags.clone()).filter(|x| !x.is_empty());
+                x
+            }));
+        }
+        add_inline_children(&mut resources, children);
+        resources
+    }
+
+    /// Compares the roles each tool grants, by scope, role and principal.
+    fn check_role_assignments(&self) -> RoleAssignmentCheck {
+        let bicep = self
+            .bicep_whatif_output
+            .iter()
+            .flat_map(|x| x.changes.iter())
+            .filter_map(|x| x.get_canonical_resource())
+            .filter(is_role_assignment)
+            .map(|x| RoleAssignmentGrant::from_bicep(&x))
+            .collect();
+        let (changes, _) = self.get_included_terraform_resources();
+        let mut terraform: Vec<RoleAssignmentGrant> = self
+            .get_terraform_canonical_resources(&changes)
+            .iter()
+            .filter(|(_, x)| is_role_assignment(x))
+            .map(|(address, x)| RoleAssignmentGrant::from_terraform(address, x))
+            .collect();
+        terraform.sort_by(|x, y| x.address.cmp(&y.address));
+        RoleAssignmentCheck::new(bicep, terraform)
+    }
+}
+
+/// Adds the children declared inline on a parent, unless they are also
+/// declared as resources of their own.
+fn add_inline_children(resources: &mut Vec<ResourceResult>, children: Vec<ResourceResult>) {
+    for child in children {
+        if !resources.iter().any(|x| x.get_affinity(&child) == 2) {
+            resources.push(child);
+        }
+    }
+}
+
+/// Takes a Bicep and a Terraform resource that satisfy `expected`,
+/// preferring the two most surely the same resource.
+fn take_matching_pair(
+    expected: &ResourceResult,
+    azure_resources: &mut Vec<ResourceResult>,
+    terraform_resources: &mut Vec<ResourceResult>,
+) -> (Option<ResourceResult>, Option<ResourceResult>) {
+    let pair = azure_resources
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.matches(expected))
+        .flat_map(|(i, x)| {
+            terraform_resources
+                .iter()
+                .enumerate()
+                .filter(|(_, y)| y.matches(expected))
+                .map(move |(j, y)| (x.get_affinity(y), i, j))
+        })
+        .filter(|(affinity, _, _)| *affinity > 0)
+        .max_by_key(|(affinity, i, j)| (*affinity, std::cmp::Reverse((*i, *j))));
+    if let Some((_, i, j)) = pair {
+        return (Some(azure_resources.swap_remove(i)), Some(terraform_resources.swap_remove(j)));
+    }
+    let take = |resources: &mut Vec<ResourceResult>, other: Option<&ResourceResult>| {
+        let index = resources
+            .iter()
+            .position(|x| x.matches(expected) && !other.is_some_and(|y| x.has_different_id(y)))?;
+        Some(resources.swap_remove(index))
+    };
+    let azure_resource = take(azure_resources, None);
+    let terraform_resource = take(terraform_resources, azure_resource.as_ref());
+    (azure_resource, terraform_resource)
+}
+
+/// Takes the other tool's resource for `resource`: the one with the same
+/// resource ID, or else one of the same type whose ID is not known to
+/// differ, preferring one with the same parent.
+fn take_counterpart(resource: &ResourceResult, resources: &mut Vec<ResourceResult>) -> Option<ResourceResult> {
+    let index = resources
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let same_type = x.resource_type == resource.resource_type && !x.has_different_id(resource);
+            (x.get_affinity(resource), same_type, i)
+        })
+        .filter(|(affinity, same_type, _)| *affinity == 2 || *same_type)
+        .max_by_key(|(affinity, _, i)| (*affinity, std::cmp::Reverse(*i)))
+        .map(|(_, _, i)| i)?;
+    Some(resources.swap_remove(index))
+}
+
+fn read_cached_output(cache_directory: &Path, file_name: &str) -> Option<String> {
+    let path = cache_directory.join(file_name);
+    match fs::read_to_string(&path) {
+        Ok(contents) => Some(contents),
+        Err(e) => {
+            debug!("Unable to read {}: {}", path.display(), e);
+            None
         }
-        result
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::resource::{split_module_address, ResourceId};
+    use std::path::Path;
     // use crate::resource::{AzureResourceChange, TerraformResourceChange};
 
     #[test]
@@ -428,6 +1381,290 @@ mod test {
         assert_eq!(tester.terraform_deployment_parameters.len(), 2);
     }
 
+    #[test]
+    pub fn test_skip_auth_pins_terraform_subscription() {
+        let mut tester = OutputTester::new();
+        tester.config.subscription_id = Some("00000000-0000-0000-0000-000000000001".to_string());
+        tester.config.tenant_id = Some("00000000-0000-0000-0000-000000000002".to_string());
+        tester.stages.authenticate = false;
+        tester.authenticate_azure_cli();
+        assert_eq!(
+            tester.terraform_environment,
+            vec![
+                ("ARM_ENVIRONMENT".to_string(), "public".to_string()),
+                ("ARM_SUBSCRIPTION_ID".to_string(), "00000000-0000-0000-0000-000000000001".to_string()),
+                ("ARM_TENANT_ID".to_string(), "00000000-0000-0000-0000-000000000002".to_string()),
+            ]
+        );
+    }
+
+    const SUBSCRIPTION_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+    /// A tester with the expected results from `tests/parameters.yaml`,
+    /// pinned to `SUBSCRIPTION_ID`.
+    fn get_comparison_tester() -> OutputTester {
+        let mut tester = OutputTester::new();
+        tester.set_application_config(ApplicationConfig::load(Path::new("tests/parameters.yaml")).unwrap());
+        tester.config.subscription_id = Some(SUBSCRIPTION_ID.to_string());
+        tester
+    }
+
+    /// The `planned_change` line `terraform plan -json` writes when it plans
+    /// to create the resource at `address`.
+    fn planned_change(address: &str) -> String {
+        let (module_calls, resource) = split_module_address(address);
+        let (resource_type, rest) = resource.split_once('.').unwrap();
+        let (name, key) = match rest.split_once('[') {
+            Some((name, key)) => (name, serde_json::from_str(key.trim_end_matches(']')).unwrap()),
+            None => (rest, Value::Null),
+        };
+        serde_json::json!({"type": "planned_change", "change": {"action": "create", "resource": {
+            "addr": address,
+            "module": module_calls.join("."),
+            "resource": resource,
+            "implied_provider": resource_type.split('_').next().unwrap(),
+            "resource_type": resource_type,
+            "resource_name": name,
+            "resource_key": key
+        }}})
+        .to_string()
+    }
+
+    /// A what-if output that creates the resources at `ids`, each with
+    /// `properties`.
+    fn whatif(ids: &[&str], properties: Value) -> Option<AzureResourceChange> {
+        let changes: Vec<Value> = ids
+            .iter()
+            .map(|id| {
+                let resource_id = ResourceId::parse(id).unwrap();
+                serde_json::json!({
+                    "after": {
+                        "apiVersion": "2023-07-01",
+                        "id": id,
+                        "name": resource_id.get_name(),
+                        "type": resource_id.get_resource_type(),
+                        "properties": properties
+                    },
+                    "changeType": "Create",
+                    "resourceId": id
+                })
+            })
+            .collect();
+        Some(serde_json::from_value(serde_json::json!({ "changes": changes })).unwrap())
+    }
+
+    fn resource_group_id(path: &str) -> String {
+        format!("/subscriptions/{}/resourceGroups/rg-test{}", SUBSCRIPTION_ID, path)
+    }
+
+    #[test]
+    pub fn test_compare_with_one_side_missing() {
+        let mut tester = get_comparison_tester();
+        assert!(tester.compare_bicep_whatif_and_terraform_plan().is_err());
+
+        tester.bicep_whatif_output = whatif(&[&resource_group_id("")], Value::Null);
+        assert!(tester.compare_bicep_whatif_and_terraform_plan().is_err());
+
+        // Only a tool that was not selected, or has no cached output, is
+        // left out of the comparison.
+        tester.stages.terraform = false;
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.compared, Provider::new().set_bicep());
+        assert_eq!(results.actual_results.len(), 3);
+        assert_eq!(results.actual_results[0].provider, Some(Provider::new().set_bicep()));
+        assert_eq!(results.actual_results[1].provider, Some(Provider::new()));
+        assert!(results.has_failures());
+
+        tester.stages.terraform = true;
+        tester.stages.from_cache = true;
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.compared, Provider::new().set_bicep());
+    }
+
+    #[test]
+    pub fn test_compare_with_selected_stage_missing() {
+        let mut tester = get_comparison_tester();
+        tester.terraform_plan_output = Some(TerraformPlanParser::parse(""));
+        assert!(tester.stages.bicep);
+        let error = tester.compare_bicep_whatif_and_terraform_plan().unwrap_err();
+        assert!(error.to_string().contains("Bicep WhatIf was selected"));
+
+        tester.stages.bicep = false;
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.compared, Provider::new().set_terraform());
+    }
+
+    #[test]
+    pub fn test_compare_by_terraform_address() {
+        let mut tester = get_comparison_tester();
+        tester.stages.bicep = false;
+        tester.suite.expected_results = serde_yaml::from_str(
+            r#"
+            - type: subnet
+              address: module.network.azurerm_subnet.this
+            - type: subnet
+              address: module.network
+            "#,
+        )
+        .unwrap();
+        let output = [
+            planned_change(r#"module.network.azurerm_subnet.this["app"]"#),
+            planned_change(r#"module.network.azurerm_subnet.this["db"]"#),
+            planned_change("azurerm_subnet.this[0]"),
+        ]
+        .join("\n");
+        tester.terraform_plan_output = Some(TerraformPlanParser::parse(&output));
+
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.actual_results.len(), 3);
+        assert_eq!(
+            results.actual_results[0].address.as_deref(),
+            Some(r#"module.network.azurerm_subnet.this["app"]"#)
+        );
+        assert_eq!(results.actual_results[1].module.as_deref(), Some("module.network"));
+        assert_eq!(results.actual_results[2].is_expected, Some(false));
+        assert_eq!(results.actual_results[2].address.as_deref(), Some("azurerm_subnet.this[0]"));
+        assert_eq!(results.modules["module.network"].matched.len(), 2);
+        assert!(results.modules["root"].matched.is_empty());
+        assert_eq!(results.modules["root"].unexpected, vec!["azurerm_subnet.this[0]"]);
+    }
+
+    #[test]
+    pub fn test_compare_lists_excluded_types() {
+        let mut tester = get_comparison_tester();
+        tester.config.cloud = crate::commands::AzureCloud::UsGovernment;
+        tester.stages.bicep = false;
+        tester.terraform_plan_output = Some(TerraformPlanParser::parse(&planned_change("azuread_application.app")));
+
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.excluded, vec!["azuread_application.app"]);
+        assert!(results.actual_results.iter().all(|x| x.address.is_none()));
+    }
+
+    #[test]
+    pub fn test_compare_matches_by_resource_id() {
+        let mut tester = get_comparison_tester();
+        tester.suite.expected_results = serde_yaml::from_str("- type: key_vault").unwrap();
+        let vault_id = |name: &str| resource_group_id(&format!("/providers/Microsoft.KeyVault/vaults/{}", name));
+        tester.bicep_whatif_output = whatif(&[&vault_id("kv-a"), &vault_id("kv-b")], Value::Null);
+        tester.terraform_plan_output = Some(TerraformPlanParser::parse(&planned_change("azurerm_key_vault.kv")));
+        tester.terraform_structured_plan = Some(serde_json::json!({"planned_values": {"root_module": {"resources": [
+            {"address": "azurerm_key_vault.kv", "values": {"name": "kv-b", "resource_group_name": "rg-test"}}
+        ]}}}));
+
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.actual_results.len(), 2);
+        assert!(results.actual_results[0].id.as_deref().unwrap().ends_with("/vaults/kv-b"));
+        assert_eq!(results.actual_results[0].address.as_deref(), Some("azurerm_key_vault.kv"));
+        assert!(results.actual_results[1].id.as_deref().unwrap().ends_with("/vaults/kv-a"));
+        assert_eq!(results.actual_results[1].provider, Some(Provider::new().set_bicep()));
+    }
+
+    #[test]
+    pub fn test_compare_does_not_pair_different_ids() {
+        let mut tester = get_comparison_tester();
+        tester.suite.expected_results = serde_yaml::from_str("- type: key_vault").unwrap();
+        let id = resource_group_id("/providers/Microsoft.KeyVault/vaults/kv-a");
+        tester.bicep_whatif_output = whatif(&[&id], Value::Null);
+        tester.terraform_plan_output = Some(TerraformPlanParser::parse(&planned_change("azurerm_key_vault.kv")));
+        tester.terraform_structured_plan = Some(serde_json::json!({"planned_values": {"root_module": {"resources": [
+            {"address": "azurerm_key_vault.kv", "values": {"name": "kv-c", "resource_group_name": "rg-test"}}
+        ]}}}));
+
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.actual_results.len(), 2);
+        assert!(results.actual_results[0].id.as_deref().unwrap().ends_with("/vaults/kv-a"));
+        assert_eq!(results.actual_results[0].provider, Some(Provider::new().set_bicep()));
+        assert!(results.actual_results[1].id.as_deref().unwrap().ends_with("/vaults/kv-c"));
+        assert_eq!(results.actual_results[1].provider, Some(Provider::new().set_terraform()));
+        assert!(results.has_failures());
+    }
+
+    #[test]
+    pub fn test_compare_inline_and_separate_children() {
+        let mut tester = get_comparison_tester();
+        tester.suite.expected_results = serde_yaml::from_str("- type: subnet\n  name: app").unwrap();
+        let id = resource_group_id("/providers/Microsoft.Network/virtualNetworks/vnet-test");
+        tester.bicep_whatif_output = whatif(&[&id], serde_json::json!({"subnets": [{"name": "app"}, {"name": "db"}]}));
+        let output = [planned_change("azurerm_virtual_network.vnet"), planned_change("azurerm_subnet.app")].join("\n");
+        tester.terraform_plan_output = Some(TerraformPlanParser::parse(&output));
+        tester.terraform_structured_plan = Some(serde_json::json!({"planned_values": {"root_module": {"resources": [
+            {"address": "azurerm_virtual_network.vnet", "values": {
+                "name": "vnet-test", "resource_group_name": "rg-test", "subnet": [{"name": "db"}]
+            }},
+            {"address": "azurerm_subnet.app", "values": {
+                "name": "app", "resource_group_name": "rg-test", "virtual_network_name": "vnet-test"
+            }}
+        ]}}}));
+
+        let results = tester.compare_bicep_whatif_and_terraform_plan().unwrap();
+        assert_eq!(results.actual_results.len(), 3);
+        assert_eq!(results.actual_results[0].address.as_deref(), Some("azurerm_subnet.app"));
+        assert_eq!(results.actual_results[0].parent.as_deref(), Some(id.as_str()));
+        let db = results
+            .actual_results
+            .iter()
+            .find(|x| x.id.as_deref().is_some_and(|x| x.ends_with("/subnets/db")))
+            .unwrap();
+        assert_eq!(db.address.as_deref(), Some("azurerm_virtual_network.vnet"));
+        assert!(results
+            .actual_results
+            .iter()
+            .all(|x| x.provider == Some(Provider::new().set_bicep().set_terraform())));
+    }
+
+    #[test]
+    pub fn test_write_run_artifacts() {
+        let directory = env::temp_dir().join(format!("infra-test-artifacts-{}", process::id()));
+        let mut tester = OutputTester::new();
+        tester.set_stages(Stages {
+            terraform: false,
+            ..Default::default()
+        });
+        tester.set_artifacts_directory(Some(directory.clone()));
+        let run = SuiteRun {
+            suite: SuiteConfig {
+                name: "smoke".to_string(),
+                ..Default::default()
+            },
+            matrix_cell: BTreeMap::from([("region".to_string(), "west us".to_string())]),
+        };
+        tester.set_suite_run(&run);
+
+        let mut commands = Vec::new();
+        let shell = TerraformCli::find(Some("sh")).unwrap();
+        let command = shell.get_command("-c").with_name("sh").with_args(vec!["echo oops >&2; exit 3"]);
+        let command = tester.run_command(command, "failing", &mut commands).unwrap();
+        assert_eq!(command.get_exit_code(), Some(3));
+
+        // A binary that is gone by the time it runs cannot be started.
+        let script = env::temp_dir().join(format!("infra-test-gone-{}.sh", process::id()));
+        fs::write(&script, "").unwrap();
+        let gone = TerraformCli::find(script.to_str()).unwrap();
+        fs::remove_file(&script).unwrap();
+        let command = gone.get_command("plan").with_name("gone");
+        assert!(tester.run_command(command, "gone", &mut commands).is_none());
+
+        tester.commands = commands;
+        tester.write_run_artifacts(&SuiteReport::new(&run, Ok(ActualResults::default())));
+        let run_directory = directory.join("smoke_region=west_us_");
+        let read = |file_name: &str| fs::read_to_string(run_directory.join(file_name)).unwrap();
+        let manifest: Value = serde_json::from_str(&read(MANIFEST_FILE)).unwrap();
+        let report: Value = serde_json::from_str(&read(REPORT_FILE)).unwrap();
+        let config = read(CONFIG_FILE);
+        let stderr = read("failing.stderr.log");
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(manifest["run"], "smoke[region=west us]");
+        assert_eq!(manifest["commands"][0]["command"], "sh -c");
+        assert_eq!(manifest["commands"][0]["exit_code"], 3);
+        assert_eq!(manifest["commands"][1]["command"], "gone plan");
+        assert_eq!(manifest["commands"][1]["exit_code"], Value::Null);
+        assert_eq!(report["status"], "passed");
+        assert!(config.contains("name: smoke"));
+        assert_eq!(stderr, "oops\n");
+    }
+
     // #[test]
     // pub fn test_compare_bicep_whatif_and_terraform_plan() {
     //     let mut tester = OutputTester::new();
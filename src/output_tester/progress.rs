@@ -0,0 +1,88 @@
+use log::info;
+use serde_json::Value;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The shortest gap between two refresh or planning updates.
+const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Summarizes the `terraform plan -json` event stream on stderr as the events
+/// arrive, e.g. "Refreshing: 37/120 resources refreshed".
+#[derive(Debug)]
+pub struct TerraformProgress {
+    prefix: String,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    refresh_started: usize,
+    refreshed: usize,
+    planned: usize,
+    reported_at: Option<Instant>,
+}
+
+impl TerraformProgress {
+    pub fn new(prefix: &str) -> Self {
+        TerraformProgress {
+            prefix: prefix.to_string(),
+            state: Mutex::new(ProgressState::default()),
+        }
+    }
+
+    pub fn handle_line(&self, line: &str) {
+        let Ok(event) = serde_json::from_str::<Value>(line) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        match event["type"].as_str() {
+            Some("refresh_start") => state.refresh_started += 1,
+            Some("refresh_complete") => state.refreshed += 1,
+            Some("planned_change") => state.planned += 1,
+            Some("change_summary") | Some("diagnostic") => {
+                if let Some(message) = event["@message"].as_str() {
+                    info!("{} {}", self.prefix, message);
+                }
+                return;
+            }
+            _ => return,
+        }
+        if state.reported_at.is_some_and(|x| x.elapsed() < REPORT_INTERVAL) {
+            return;
+        }
+        state.reported_at = Some(Instant::now());
+        info!("{} {}", self.prefix, state.get_message());
+    }
+}
+
+impl ProgressState {
+    fn get_message(&self) -> String {
+        let refreshing = format!(
+            "{}/{} resources refreshed",
+            self.refreshed, self.refresh_started
+        );
+        match (self.planned, self.refresh_started) {
+            (0, _) => format!("Refreshing: {}", refreshing),
+            (planned, 0) => format!("Planning: {} changes so far", planned),
+            (planned, _) => format!("Planning: {} changes so far, {}", planned, refreshing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod progress_test {
+    use super::*;
+
+    #[test]
+    fn test_progress_counts_events() {
+        let progress = TerraformProgress::new("[default terraform]");
+        progress.handle_line(r#"{"type":"refresh_start","hook":{}}"#);
+        progress.handle_line(r#"{"type":"refresh_start","hook":{}}"#);
+        progress.handle_line("not json");
+        progress.handle_line(r#"{"type":"refresh_complete","hook":{}}"#);
+        let state = progress.state.lock().unwrap();
+        assert_eq!(state.get_message(), "Refreshing: 1/2 resources refreshed");
+    }
+}
@@ -0,0 +1,198 @@
+use super::{ActualResults, Diagnostic, Severity, SuiteRun};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SuiteStatus {
+    #[default]
+    Passed,
+    Failed,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct SuiteReport {
+    pub name: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub matrix_cell: BTreeMap<String, String>,
+    pub status: SuiteStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tool_versions: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drift: Vec<DriftedResource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<ActualResults>,
+}
+
+impl SuiteReport {
+    pub fn new(run: &SuiteRun, result: Result<ActualResults>) -> Self {
+        match result {
+            Ok(results) => {
+                let status = if results.has_failures() {
+                    SuiteStatus::Failed
+                } else {
+                    SuiteStatus::Passed
+                };
+                SuiteReport {
+                    name: run.suite.name.clone(),
+                    matrix_cell: run.matrix_cell.clone(),
+                    status,
+                    error: None,
+                    results: Some(results),
+                    ..Default::default()
+                }
+            }
+            Err(e) => SuiteReport {
+                name: run.suite.name.clone(),
+                matrix_cell: run.matrix_cell.clone(),
+                status: SuiteStatus::Error,
+                error: Some(e.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A resource whose real state no longer matches the Terraform state, as
+/// reported by a `resource_drift` event.  Drift comes from changes made
+/// outside Terraform, not from differences between the templates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct DriftedResource {
+    pub address: String,
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    pub action: String,
+}
+
+impl SuiteReport {
+    /// Adds the drifted resources.  They are informational next to a
+    /// comparison, but are the finding of a refresh-only run, so there they
+    /// fail it.
+    pub fn add_drift(&mut self, drift: Vec<DriftedResource>, refresh_only: bool) {
+        if refresh_only && self.status == SuiteStatus::Passed && !drift.is_empty() {
+            self.status = SuiteStatus::Failed;
+        }
+        self.drift.extend(drift);
+    }
+
+    /// Adds the tools' diagnostics.  Errors fail a run that otherwise
+    /// passed, and so do warnings when `fail_on_warnings` is set.
+    pub fn add_diagnostics(&mut self, diagnostics: Vec<Diagnostic>, fail_on_warnings: bool) {
+        let threshold = if fail_on_warnings {
+            Severity::Warning
+        } else {
+            Severity::Error
+        };
+        if self.status == SuiteStatus::Passed && diagnostics.iter().any(|x| x.severity >= threshold) {
+            self.status = SuiteStatus::Failed;
+        }
+        self.diagnostics.extend(diagnostics);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub passed: usize,
+    pub failed: usize,
+    pub errors: usize,
+    pub suites: Vec<SuiteReport>,
+}
+
+impl RunReport {
+    pub fn add(&mut self, suite: SuiteReport) {
+        match suite.status {
+            SuiteStatus::Passed => self.passed += 1,
+            SuiteStatus::Failed => self.failed += 1,
+            SuiteStatus::Error => self.errors += 1,
+        }
+        self.suites.push(suite);
+    }
+
+    pub fn success(&self) -> bool {
+        self.failed == 0 && self.errors == 0
+    }
+}
+
+#[cfg(test)]
+mod report_test {
+    use super::*;
+
+    fn diagnostic(severity: Severity) -> Diagnostic {
+        Diagnostic {
+            tool: "terraform".to_string(),
+            severity,
+            code: None,
+            summary: "Deprecated attribute".to_string(),
+            detail: None,
+            address: None,
+            file: None,
+            range: None,
+        }
+    }
+
+    fn drift() -> Vec<DriftedResource> {
+        vec![DriftedResource {
+            address: "azurerm_resource_group.rg".to_string(),
+            resource_type: "azurerm_resource_group".to_string(),
+            action: "update".to_string(),
+        }]
+    }
+
+    #[test]
+    fn test_add_diagnostics() {
+        let run = SuiteRun::default();
+        let mut report = SuiteReport::new(&run, Ok(ActualResults::default()));
+        report.add_diagnostics(vec![diagnostic(Severity::Warning)], false);
+        assert_eq!(report.status, SuiteStatus::Passed);
+        assert_eq!(report.diagnostics.len(), 1);
+        report.add_diagnostics(vec![diagnostic(Severity::Warning)], true);
+        assert_eq!(report.status, SuiteStatus::Failed);
+
+        let mut report = SuiteReport::new(&run, Ok(ActualResults::default()));
+        report.add_diagnostics(vec![diagnostic(Severity::Error)], false);
+        assert_eq!(report.status, SuiteStatus::Failed);
+
+        let mut report = SuiteReport::new(&run, Err(anyhow::anyhow!("Terraform Plan failed.")));
+        report.add_diagnostics(vec![diagnostic(Severity::Error)], false);
+        assert_eq!(report.status, SuiteStatus::Error);
+        assert_eq!(report.error.as_deref(), Some("Terraform Plan failed."));
+    }
+
+    #[test]
+    fn test_add_drift() {
+        let run = SuiteRun::default();
+        let mut report = SuiteReport::new(&run, Ok(ActualResults::default()));
+        report.add_drift(drift(), false);
+        assert_eq!(report.status, SuiteStatus::Passed);
+        assert_eq!(report.drift.len(), 1);
+
+        let mut report = SuiteReport::new(&run, Ok(ActualResults::default()));
+        report.add_drift(Vec::new(), true);
+        assert_eq!(report.status, SuiteStatus::Passed);
+        report.add_drift(drift(), true);
+        assert_eq!(report.status, SuiteStatus::Failed);
+    }
+
+    #[test]
+    fn test_run_report_success() {
+        let run = SuiteRun::default();
+        let mut run_report = RunReport::default();
+        run_report.add(SuiteReport::new(&run, Ok(ActualResults::default())));
+        assert!(run_report.success());
+
+        let mut failed = SuiteReport::new(&run, Ok(ActualResults::default()));
+        failed.add_drift(drift(), true);
+        run_report.add(failed);
+        run_report.add(SuiteReport::new(&run, Err(anyhow::anyhow!("No output."))));
+        assert_eq!((run_report.passed, run_report.failed, run_report.errors), (1, 1, 1));
+        assert!(!run_report.success());
+    }
+}
@@ -0,0 +1,321 @@
+use super::GUID_REGEX;
+use crate::resource::CanonicalResource;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+const ROLE_ASSIGNMENT_TYPE: &str = "Microsoft.Authorization/roleAssignments";
+
+/// Built-in role names and their role definition GUIDs, which are the same
+/// in every tenant and cloud.
+const BUILT_IN_ROLES: &[(&str, &str)] = &[
+    ("Owner", "8e3af657-a8ff-443c-a75c-2fe8c4bcb635"),
+    ("Contributor", "b24988ac-6180-42a0-ab88-20f7382dd24c"),
+    ("Reader", "acdd72a7-3385-48ef-bd42-f606fba81ae7"),
+    ("User Access Administrator", "18d7d88d-d35e-4fb5-a5c3-7773c20a72d9"),
+    ("Role Based Access Control Administrator", "f58310d9-a9f6-439a-9e8d-f62e7b41a168"),
+    ("AcrPull", "7f951dda-4ed3-4680-a7ca-43fe172d538d"),
+    ("AcrPush", "8311e382-0749-4cb8-b61a-304f252e45ec"),
+    ("Azure Kubernetes Service Cluster Admin Role", "0ab0b1a8-8aac-4efd-b8c2-3ee1fb270be8"),
+    ("Azure Kubernetes Service Cluster User Role", "4abbcc35-e782-43d8-92c5-2d3f1bd2253f"),
+    ("Azure Kubernetes Service Contributor Role", "ed7f3fbd-7b88-4dd4-9017-9adb7ce333f8"),
+    ("Azure Kubernetes Service RBAC Admin", "3498e952-d568-435e-9b2c-8d77e338d7f7"),
+    ("Azure Kubernetes Service RBAC Cluster Admin", "b1ff04bb-8a4e-4dc4-8eb5-8693973ce19b"),
+    ("Azure Kubernetes Service RBAC Reader", "7f6c6a51-bcf8-42ba-9220-52d62157d7db"),
+    ("Azure Kubernetes Service RBAC Writer", "a7ffa36f-339b-4b5c-8bdf-e2c188b2c0eb"),
+    ("Key Vault Administrator", "00482a5a-887f-4fb3-b363-3b7fe8e74483"),
+    ("Key Vault Certificates Officer", "a4417e6f-fecd-4de8-b567-7b0420556985"),
+    ("Key Vault Crypto Officer", "14b46e9e-c2b7-41b4-b07b-48a6ebf60603"),
+    ("Key Vault Crypto Service Encryption User", "e147488a-f6f5-4113-8e2d-b22465e65bf6"),
+    ("Key Vault Crypto User", "12338af0-0e69-4776-bea7-57ae8d297424"),
+    ("Key Vault Reader", "21090545-7ca7-4776-b22c-e363652d74d2"),
+    ("Key Vault Secrets Officer", "b86a8fe4-44ce-4948-aee5-eccb2c155cd7"),
+    ("Key Vault Secrets User", "4633458b-17de-408a-b874-0445c86b69e6"),
+    ("Managed Identity Contributor", "e40ec5ca-96e0-45a2-b4ff-59039f2c2b59"),
+    ("Managed Identity Operator", "f1a07417-d97a-45cb-824c-7a7467783830"),
+    ("Network Contributor", "4d97b98b-1d4f-4787-a291-c67834d212e7"),
+    ("Virtual Machine Contributor", "9980e02c-c2be-4d73-94e8-173b1dc7cf3c"),
+    ("Storage Account Contributor", "17d1049b-9a84-46fb-8f53-869881c3d3ab"),
+    ("Storage Blob Data Contributor", "ba92f5b4-2d11-453d-a403-e96b0029c9fe"),
+    ("Storage Blob Data Owner", "b7e6dc6d-f1e8-4753-8033-0f276bb0955b"),
+    ("Storage Blob Data Reader", "2a2b9908-6ea1-4ae2-8e65-a410df84e7d1"),
+    ("Storage Queue Data Contributor", "974c5e8b-45b9-4653-ba55-5f855dd0fb88"),
+    ("Storage Table Data Contributor", "0a9a7e1f-b9d0-4cc4-a60d-0319b160aaa3"),
+    ("Monitoring Contributor", "749f88d5-cbae-40b8-bcfc-e573ddc772fa"),
+    ("Monitoring Metrics Publisher", "3913510d-42f4-4e42-8a64-420c390055eb"),
+    ("Monitoring Reader", "43d0d8ad-25c7-4714-9337-8ba259a9fe05"),
+    ("Log Analytics Contributor", "92aaf0da-9dab-42b6-94a3-d43ce8d16293"),
+    ("Log Analytics Reader", "73c42c96-874c-492b-b04d-ab87d138a893"),
+    ("Azure Service Bus Data Owner", "090c5cfd-751d-490a-894a-3ce6f1109419"),
+    ("Azure Service Bus Data Receiver", "4f6d3b9b-027b-4f4c-9142-0e5a2a2247e0"),
+    ("Azure Service Bus Data Sender", "69a216fc-b8fb-44d8-bc22-1f3c2cd27a39"),
+    ("Azure Event Hubs Data Owner", "f526a384-b230-433a-b45c-95f59c4a2dec"),
+    ("Azure Event Hubs Data Receiver", "a638d3c7-ab3a-418d-83e6-5f17a39d4fde"),
+    ("Azure Event Hubs Data Sender", "2b629674-e913-4c01-ae53-ef4638d8f975"),
+    ("Cognitive Services OpenAI User", "5e0bd9bd-7b93-4f28-af87-19fc36ad61bd"),
+    ("Cognitive Services User", "a97b65f3-24c7-4388-baec-2e87135dc908"),
+    ("App Configuration Data Owner", "5ae67dd6-50cb-40e7-96ff-dc2bfa4b606b"),
+    ("App Configuration Data Reader", "516239f1-63e1-4d78-a4de-a74fb236a071"),
+    ("Search Index Data Contributor", "8ebe5a00-799e-43f5-93ac-243d3dce84a7"),
+    ("Search Index Data Reader", "1407120a-92aa-4202-b7e9-c0e197c71c8f"),
+    ("Website Contributor", "de139f84-1756-47ae-9be6-808fbbe84772"),
+];
+
+/// A role one tool grants: the role definition, by GUID when it is a
+/// built-in role or given by ID, to a principal at a scope.  The role,
+/// scope and principal are `None` when they are only known after apply,
+/// and the role also when Terraform names a role that is not built in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RoleAssignmentGrant {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(rename = "roleDefinition", skip_serializing_if = "Option::is_none")]
+    pub role_definition: Option<String>,
+    #[serde(rename = "roleName", skip_serializing_if = "Option::is_none")]
+    pub role_name: Option<String>,
+    #[serde(rename = "principalId", skip_serializing_if = "Option::is_none")]
+    pub principal_id: Option<String>,
+    /// The Terraform address of the grant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+impl RoleAssignmentGrant {
+    /// The grant a role assignment Bicep deploys.
+    pub fn from_bicep(resource: &CanonicalResource) -> Self {
+        let properties = &resource.properties;
+        let role_definition = properties["roleDefinitionId"].as_str().unwrap_or_default();
+        RoleAssignmentGrant {
+            scope: resource.parent.as_ref().map(|x| x.to_string()),
+            role_definition: get_role_definition(role_definition),
+            role_name: get_role_name(role_definition),
+            principal_id: get_principal_id(properties["principalId"].as_str()),
+            address: None,
+        }
+    }
+
+    /// The grant an `azurerm_role_assignment` plans, which names its role
+    /// either by `role_definition_name` or by `role_definition_id`.  Bicep
+    /// only has the GUID, so a role named but not in the built-in table,
+    /// such as a custom role, is only compared by name, with the name the
+    /// table gives Bicep's GUID.
+    pub fn from_terraform(address: &str, resource: &CanonicalResource) -> Self {
+        let values = &resource.properties;
+        let role_name = values["role_definition_name"].as_str();
+        let role_definition = values["role_definition_id"].as_str().or(role_name).unwrap_or_default();
+        let grant = RoleAssignmentGrant {
+            scope: resource.parent.as_ref().map(|x| x.to_string()),
+            role_definition: get_role_definition(role_definition),
+            role_name: get_role_name(role_definition).or(role_name.map(|x| x.to_string())),
+            principal_id: get_principal_id(values["principal_id"].as_str()),
+            address: Some(address.to_string()),
+        };
+        if let (None, Some(role_name)) = (&grant.role_definition, role_name) {
+            warn!("{} grants {}, which is not a built-in role. Comparing it by name only.", address, role_name);
+        }
+        grant
+    }
+
+    /// Whether both grants give the same role: by GUID when both know it,
+    /// or else by name, or `None` when the role cannot be compared, such
+    /// as a custom role Bicep gives by GUID and Terraform by name.
+    fn is_same_role(&self, other: &RoleAssignmentGrant) -> Option<bool> {
+        [(&self.role_definition, &other.role_definition), (&self.role_name, &other.role_name)]
+            .into_iter()
+            .find_map(|(x, y)| Some(x.as_ref()?.eq_ignore_ascii_case(y.as_ref()?)))
+    }
+
+    /// How many of the role, scope and principal both grants are known to
+    /// share, or `None` when they differ in any that both know.
+    fn get_agreement(&self, other: &RoleAssignmentGrant) -> Option<usize> {
+        let mut agreement = match self.is_same_role(other) {
+            Some(false) => return None,
+            Some(true) => 1,
+            None => 0,
+        };
+        for (x, y) in [(&self.scope, &other.scope), (&self.principal_id, &other.principal_id)] {
+            if let (Some(x), Some(y)) = (x, y) {
+                if !x.eq_ignore_ascii_case(y) {
+                    return None;
+                }
+                agreement += 1;
+            }
+        }
+        Some(agreement)
+    }
+}
+
+/// The role assignments both tools make.  `missing` lists the grants Bicep
+/// makes that Terraform does not, and `extra` those only Terraform makes.
+/// `inconclusive` lists the Terraform grants that pair with a Bicep grant
+/// only when their roles, which cannot be compared, are left out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RoleAssignmentCheck {
+    pub matched: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inconclusive: Vec<RoleAssignmentGrant>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<RoleAssignmentGrant>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<RoleAssignmentGrant>,
+}
+
+impl RoleAssignmentCheck {
+    /// Pairs the grants with the most in common first, so a grant whose
+    /// role, scope or principal is unknown only pairs with what is left.
+    pub fn new(bicep: Vec<RoleAssignmentGrant>, terraform: Vec<RoleAssignmentGrant>) -> Self {
+        let mut pairs: Vec<(usize, usize, usize)> = bicep
+            .iter()
+            .enumerate()
+            .flat_map(|(i, x)| terraform.iter().enumerate().filter_map(move |(j, y)| Some((x.get_agreement(y)?, i, j))))
+            .collect();
+        pairs.sort_by_key(|(agreement, i, j)| (Reverse(*agreement), *i, *j));
+
+        let mut bicep_matched = vec![false; bicep.len()];
+        let mut terraform_matched = vec![false; terraform.len()];
+        let mut matched = 0;
+        let mut inconclusive = Vec::new();
+        for (_, i, j) in pairs {
+            if !bicep_matched[i] && !terraform_matched[j] {
+                bicep_matched[i] = true;
+                terraform_matched[j] = true;
+                match bicep[i].is_same_role(&terraform[j]) {
+                    Some(_) => matched += 1,
+                    None => inconclusive.push(terraform[j].clone()),
+                }
+            }
+        }
+        let unmatched = |grants: Vec<RoleAssignmentGrant>, matched: &[bool]| {
+            grants.into_iter().zip(matched).filter(|(_, matched)| !**matched).map(|(x, _)| x).collect()
+        };
+        RoleAssignmentCheck {
+            matched,
+            inconclusive,
+            missing: unmatched(bicep, &bicep_matched),
+            extra: unmatched(terraform, &terraform_matched),
+        }
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// True for a resolved role assignment, or one whose scope, and so whose
+/// ARM type, Terraform only learns after apply.
+pub fn is_role_assignment(resource: &CanonicalResource) -> bool {
+    resource.resource_type.eq_ignore_ascii_case(ROLE_ASSIGNMENT_TYPE) || resource.resource_type == "azurerm_role_assignment"
+}
+
+/// The GUID of a role definition given by ID or built-in name, or `None`
+/// for any other name and for an ID that is only known after apply.
+fn get_role_definition(role: &str) -> Option<String> {
+    let guid = role.rsplit('/').next().unwrap_or_default();
+    if GUID_REGEX.is_match(guid) {
+        return Some(guid.to_lowercase());
+    }
+    BUILT_IN_ROLES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(role))
+        .map(|(_, guid)| guid.to_string())
+}
+
+fn get_role_name(role: &str) -> Option<String> {
+    let guid = get_role_definition(role)?;
+    BUILT_IN_ROLES.iter().find(|(_, x)| *x == guid).map(|(name, _)| name.to_string())
+}
+
+/// The principal's object ID.  Bicep leaves a principal it cannot resolve
+/// as a template expression, and Terraform leaves it unknown.
+fn get_principal_id(principal_id: Option<&str>) -> Option<String> {
+    principal_id.filter(|x| GUID_REGEX.is_match(x)).map(|x| x.to_lowercase())
+}
+
+#[cfg(test)]
+mod role_assignments_test {
+    use super::*;
+    use crate::resource::ResourceId;
+
+    const SCOPE: &str = "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.KeyVault/vaults/kv-test";
+    const PRINCIPAL_ID: &str = "11111111-1111-1111-1111-111111111111";
+
+    fn bicep_grant(role_guid: &str, principal_id: &str) -> RoleAssignmentGrant {
+        RoleAssignmentGrant::from_bicep(&CanonicalResource {
+            parent: ResourceId::parse(SCOPE),
+            resource_type: ROLE_ASSIGNMENT_TYPE.to_string(),
+            properties: serde_json::json!({
+                "roleDefinitionId": format!("/subscriptions/00000000-0000-0000-0000-000000000000/providers/Microsoft.Authorization/roleDefinitions/{}", role_guid),
+                "principalId": principal_id
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn terraform_grant(address: &str, parent: Option<&str>, values: serde_json::Value) -> RoleAssignmentGrant {
+        RoleAssignmentGrant::from_terraform(address, &CanonicalResource {
+            parent: parent.and_then(ResourceId::parse),
+            resource_type: ROLE_ASSIGNMENT_TYPE.to_string(),
+            properties: values,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_role_assignment_check() {
+        let bicep = vec![
+            bicep_grant("00482a5a-887f-4fb3-b363-3b7fe8e74483", PRINCIPAL_ID),
+            bicep_grant("4633458b-17de-408a-b874-0445c86b69e6", "[reference('id').principalId]"),
+        ];
+        assert_eq!(bicep[0].role_name.as_deref(), Some("Key Vault Administrator"));
+        assert_eq!(bicep[1].principal_id, None);
+
+        let terraform = vec![
+            terraform_grant(
+                "azurerm_role_assignment.admin",
+                Some(SCOPE),
+                serde_json::json!({"role_definition_name": "Key Vault Administrator", "principal_id": PRINCIPAL_ID.to_uppercase()}),
+            ),
+            terraform_grant(
+                "azurerm_role_assignment.reader",
+                None,
+                serde_json::json!({"role_definition_name": "Key Vault Reader", "principal_id": null}),
+            ),
+        ];
+        let check = RoleAssignmentCheck::new(bicep, terraform);
+        assert_eq!(check.matched, 1);
+        assert_eq!(check.missing[0].role_name.as_deref(), Some("Key Vault Secrets User"));
+        assert_eq!(check.extra[0].address.as_deref(), Some("azurerm_role_assignment.reader"));
+        assert_eq!(check.extra[0].role_definition.as_deref(), Some("21090545-7ca7-4776-b22c-e363652d74d2"));
+        assert!(!check.is_consistent());
+    }
+
+    #[test]
+    fn test_role_not_built_in() {
+        let bicep = vec![bicep_grant("7f3e1c2a-1b2c-4d5e-8f90-0a1b2c3d4e5f", PRINCIPAL_ID)];
+        let terraform = vec![terraform_grant(
+            "azurerm_role_assignment.custom",
+            Some(SCOPE),
+            serde_json::json!({"role_definition_name": "Custom Secrets Operator", "principal_id": PRINCIPAL_ID}),
+        )];
+        assert_eq!(terraform[0].role_definition, None);
+        assert_eq!(terraform[0].role_name.as_deref(), Some("Custom Secrets Operator"));
+        let check = RoleAssignmentCheck::new(bicep, terraform);
+        assert_eq!(check.matched, 0);
+        assert_eq!(check.inconclusive[0].address.as_deref(), Some("azurerm_role_assignment.custom"));
+        assert!(check.is_consistent());
+
+        // A role Bicep names through the table is compared by name.
+        let bicep = vec![bicep_grant("00482a5a-887f-4fb3-b363-3b7fe8e74483", PRINCIPAL_ID)];
+        let terraform = vec![terraform_grant(
+            "azurerm_role_assignment.contributor",
+            Some(SCOPE),
+            serde_json::json!({"role_definition_name": "key vault contributor", "principal_id": PRINCIPAL_ID}),
+        )];
+        let check = RoleAssignmentCheck::new(bicep, terraform);
+        assert_eq!(check.matched, 0);
+        assert!(check.inconclusive.is_empty());
+        assert_eq!(check.missing[0].role_name.as_deref(), Some("Key Vault Administrator"));
+        assert_eq!(check.extra[0].role_name.as_deref(), Some("key vault contributor"));
+        assert!(!check.is_consistent());
+    }
+}
@@ -0,0 +1,161 @@
+use super::{OutputTester, SuiteReport, SuiteRun};
+use crate::commands::is_cancelled;
+use log::info;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+/// Runs every suite run against a clone of `base_tester`, at most `jobs` at a
+/// time.  Reports come back in the same order as `runs`.  Runs that have
+/// not started when the commands are cancelled are left out.
+pub fn run_suites(base_tester: &OutputTester, runs: Vec<SuiteRun>, jobs: usize) -> Vec<SuiteReport> {
+    run_in_parallel(runs, jobs, |run| run_suite(base_tester, run))
+}
+
+/// The suite runs still to start and the Terraform directories in use.
+struct RunQueue {
+    pending: VecDeque<(usize, SuiteRun)>,
+    busy: HashSet<PathBuf>,
+}
+
+/// Releases a run's Terraform directory when it finishes, even by panicking,
+/// so runs waiting for it are not left waiting forever.
+struct BusyDirectory<'a> {
+    queue: &'a Mutex<RunQueue>,
+    ready: &'a Condvar,
+    directory: PathBuf,
+}
+
+impl Drop for BusyDirectory<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        queue.busy.remove(&self.directory);
+        self.ready.notify_all();
+    }
+}
+
+/// Calls `run` for every suite run, at most `jobs` at a time and never for
+/// two runs that share a Terraform directory, as they would contend for its
+/// `.terraform` directory, lock file and state lock.  Results come back in
+/// the same order as `runs`.
+fn run_in_parallel<T: Send>(runs: Vec<SuiteRun>, jobs: usize, run: impl Fn(&SuiteRun) -> T + Sync) -> Vec<T> {
+    let queue = Mutex::new(RunQueue {
+        pending: runs.into_iter().enumerate().collect(),
+        busy: HashSet::new(),
+    });
+    let ready = Condvar::new();
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| loop {
+                let (index, suite_run, directory) = {
+                    let mut queue = queue.lock().unwrap();
+                    loop {
+                        if queue.pending.is_empty() || is_cancelled() {
+                            return;
+                        }
+                        let available = queue
+                            .pending
+                            .iter()
+                            .position(|(_, x)| !queue.busy.contains(&get_terraform_directory(x)));
+                        if let Some((index, suite_run)) = available.and_then(|x| queue.pending.remove(x)) {
+                            let directory = get_terraform_directory(&suite_run);
+                            queue.busy.insert(directory.clone());
+                            break (index, suite_run, directory);
+                        }
+                        queue = ready.wait(queue).unwrap();
+                    }
+                };
+                let _busy = BusyDirectory {
+                    queue: &queue,
+                    ready: &ready,
+                    directory,
+                };
+                let result = run(&suite_run);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn get_terraform_directory(run: &SuiteRun) -> PathBuf {
+    let path = PathBuf::from(run.suite.terraform_path.clone().unwrap_or_default());
+    fs::canonicalize(&path).unwrap_or(path)
+}
+
+fn run_suite(base_tester: &OutputTester, run: &SuiteRun) -> SuiteReport {
+    info!("Running suite {}.", run.get_name());
+    let mut tester = base_tester.clone();
+    let result = tester
+        .set_suite_run(run)
+        .check_tool_versions()
+        .set_deployment_parameters()
+        .execute_bicep_whatif_and_terraform_plan()
+        .compare_bicep_whatif_and_terraform_plan();
+    let mut report = SuiteReport::new(run, result);
+    report.tool_versions = tester.get_tool_versions().clone();
+    report.add_diagnostics(tester.get_diagnostics(), tester.get_config().fail_on_warnings);
+    report.add_drift(tester.get_drift(), tester.get_stages().refresh_only);
+    tester.write_run_artifacts(&report);
+    report
+}
+
+#[cfg(test)]
+mod runner_test {
+    use super::*;
+    use crate::output_tester::SuiteConfig;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_run_in_parallel() {
+        let runs: Vec<SuiteRun> = ["a", "a", "b", "b", "c", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, path)| SuiteRun {
+                suite: SuiteConfig {
+                    name: format!("suite{}", i),
+                    terraform_path: Some(format!("./infra/{}", path)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .collect();
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+        let running_by_path: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+        let most_running_by_path = AtomicUsize::new(0);
+
+        let names = run_in_parallel(runs, 2, |run| {
+            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most_running.fetch_max(now_running, Ordering::SeqCst);
+            let path = run.suite.terraform_path.clone().unwrap();
+            {
+                let mut running_by_path = running_by_path.lock().unwrap();
+                let count = running_by_path.entry(path.clone()).or_default();
+                *count += 1;
+                most_running_by_path.fetch_max(*count, Ordering::SeqCst);
+            }
+            thread::sleep(Duration::from_millis(20));
+            *running_by_path.lock().unwrap().get_mut(&path).unwrap() -= 1;
+            running.fetch_sub(1, Ordering::SeqCst);
+            run.get_name()
+        });
+
+        assert_eq!(names, vec!["suite0", "suite1", "suite2", "suite3", "suite4", "suite5"]);
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+        assert_eq!(most_running_by_path.load(Ordering::SeqCst), 1);
+    }
+}
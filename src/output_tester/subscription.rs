@@ -0,0 +1,140 @@
+use crate::resource::{AzureResourceChange, ResourceId};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+lazy_static! {
+    pub(super) static ref GUID_REGEX: Regex =
+        Regex::new(r"(?i)^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap();
+}
+
+/// The data sources that name the subscription Terraform is using.
+const SUBSCRIPTION_DATA_SOURCES: &[&str] = &["azurerm_client_config", "azurerm_subscription"];
+
+/// The subscriptions each tool planned against.  Terraform only reveals its
+/// subscription through resource IDs that are already known at plan time,
+/// so an empty `terraform` list means the check could not be made.  Only
+/// the resources' own IDs count, as their attributes can legitimately refer
+/// to other subscriptions, e.g. a peered hub network or a central Log
+/// Analytics workspace.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct SubscriptionCheck {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    pub bicep: BTreeSet<String>,
+    pub terraform: BTreeSet<String>,
+    pub consistent: bool,
+}
+
+impl SubscriptionCheck {
+    pub fn new(
+        expected: Option<&str>,
+        whatif: Option<&AzureResourceChange>,
+        structured_plan: Option<&Value>,
+    ) -> Self {
+        let mut bicep = BTreeSet::new();
+        for change in whatif.iter().flat_map(|x| x.changes.iter()) {
+            collect_from_id(&change.resource_id, &mut bicep);
+        }
+        let mut terraform = BTreeSet::new();
+        if let Some(plan) = structured_plan {
+            for change in plan["resource_changes"].as_array().into_iter().flatten() {
+                for values in [&change["change"]["before"], &change["change"]["after"]] {
+                    collect_from_resource(&change["type"], values, &mut terraform);
+                }
+            }
+            collect_from_module(&plan["prior_state"]["values"]["root_module"], &mut terraform);
+        }
+
+        let expected = expected.map(|x| x.to_lowercase());
+        let consistent = match &expected {
+            Some(id) => bicep.iter().chain(terraform.iter()).all(|x| x == id),
+            None => bicep.union(&terraform).count() <= 1,
+        };
+        SubscriptionCheck {
+            expected,
+            bicep,
+            terraform,
+            consistent,
+        }
+    }
+}
+
+/// The subscription a resource ID is in, ignoring any other subscription a
+/// nested ID names.
+fn collect_from_id(id: &str, ids: &mut BTreeSet<String>) {
+    let id = ResourceId::parse(id);
+    if let Some(subscription_id) = id.as_ref().and_then(|x| x.get_subscription_id()).filter(|x| GUID_REGEX.is_match(x)) {
+        ids.insert(subscription_id.to_lowercase());
+    }
+}
+
+fn collect_from_resource(resource_type: &Value, values: &Value, ids: &mut BTreeSet<String>) {
+    if let Some(id) = values["id"].as_str() {
+        collect_from_id(id, ids);
+    }
+    if resource_type.as_str().is_some_and(|x| SUBSCRIPTION_DATA_SOURCES.contains(&x)) {
+        if let Some(id) = values["subscription_id"].as_str().filter(|id| GUID_REGEX.is_match(id)) {
+            ids.insert(id.to_lowercase());
+        }
+    }
+}
+
+fn collect_from_module(module: &Value, ids: &mut BTreeSet<String>) {
+    for resource in module["resources"].as_array().into_iter().flatten() {
+        collect_from_resource(&resource["type"], &resource["values"], ids);
+    }
+    for child in module["child_modules"].as_array().into_iter().flatten() {
+        collect_from_module(child, ids);
+    }
+}
+
+#[cfg(test)]
+mod subscription_test {
+    use super::*;
+
+    #[test]
+    fn test_subscription_check() {
+        let whatif: AzureResourceChange = serde_json::from_str(
+            r#"{"changes": [{
+                "changeType": "Create",
+                "resourceId": "/subscriptions/13AE0661-466F-4189-9095-CBD2E68A485F/resourceGroups/rg-nevermore"
+            }]}"#,
+        )
+        .unwrap();
+        let plan: Value = serde_json::from_str(
+            r#"{
+                "prior_state": {"values": {"root_module": {"resources": [{
+                    "address": "data.azurerm_client_config.current",
+                    "type": "azurerm_client_config",
+                    "values": {"subscription_id": "13ae0661-466f-4189-9095-cbd2e68a485f"}
+                }]}}},
+                "resource_changes": [{
+                    "address": "azurerm_virtual_network_peering.to_hub",
+                    "type": "azurerm_virtual_network_peering",
+                    "change": {"before": null, "after": {
+                        "remote_virtual_network_id": "/subscriptions/99999999-0000-0000-0000-000000000000/resourceGroups/rg-hub/providers/Microsoft.Network/virtualNetworks/vnet-hub"
+                    }}
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let check = SubscriptionCheck::new(
+            Some("13ae0661-466f-4189-9095-cbd2e68a485f"),
+            Some(&whatif),
+            Some(&plan),
+        );
+        assert!(check.consistent);
+        assert_eq!(check.bicep.len(), 1);
+        assert_eq!(check.terraform.len(), 1);
+
+        let check = SubscriptionCheck::new(
+            Some("00000000-0000-0000-0000-000000000000"),
+            Some(&whatif),
+            Some(&plan),
+        );
+        assert!(!check.consistent);
+    }
+}
@@ -0,0 +1,232 @@
+use super::ResourceResult;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The tags every taggable resource must carry, set by `tagPolicy`.  Keys
+/// are compared case-insensitively, as Azure does, and ignored keys, by
+/// default the `azd-env-name` tag azd adds, are left out of both the parity
+/// check and the policy.  Tags the tools set differently fail a run unless
+/// `compare` is turned off.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TagPolicy {
+    #[serde(default = "default_compare")]
+    pub compare: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+    #[serde(rename = "allowedValues", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub allowed_values: BTreeMap<String, Vec<String>>,
+    #[serde(default = "default_ignored_tags")]
+    pub ignore: Vec<String>,
+}
+
+impl Default for TagPolicy {
+    fn default() -> Self {
+        TagPolicy {
+            compare: true,
+            required: Vec::new(),
+            allowed_values: BTreeMap::new(),
+            ignore: default_ignored_tags(),
+        }
+    }
+}
+
+fn default_compare() -> bool {
+    true
+}
+
+fn default_ignored_tags() -> Vec<String> {
+    vec!["azd-env-name".to_string()]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TagDiscrepancyKind {
+    /// The tools set different values, or only one sets the tag.
+    Differs,
+    /// A required tag is missing.
+    Required,
+    /// A value is not among the allowed values.
+    NotAllowed,
+}
+
+/// A tag that differs between the tools or breaks the policy, with its
+/// value in each tool that plans the resource.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TagDiscrepancy {
+    pub key: String,
+    pub kind: TagDiscrepancyKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bicep: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terraform: Option<String>,
+}
+
+impl TagPolicy {
+    /// The tag discrepancies of a resource as each tool plans it, or `None`
+    /// for a tool that does not plan it.  A resource whose tags a tool does
+    /// not know, such as an inline child, is only checked for the other
+    /// tool, and the tags are only compared when Terraform knows them.
+    /// What-if leaves out an empty `tags`, so Bicep's are taken as empty
+    /// when Terraform shows the resource can be tagged.  A tag whose value
+    /// Terraform only knows after apply is taken as set to any value.
+    pub fn check(&self, bicep: Option<&ResourceResult>, terraform: Option<&ResourceResult>) -> Vec<TagDiscrepancy> {
+        let unknown: BTreeSet<String> = terraform
+            .iter()
+            .flat_map(|x| x.unknown_tags.iter())
+            .map(|x| x.to_lowercase())
+            .collect();
+        let terraform = terraform.and_then(|x| x.tags.as_ref()).map(|x| self.normalize(x));
+        let bicep = bicep
+            .and_then(|x| x.tags.as_ref().map(|x| self.normalize(x)))
+            .or_else(|| bicep.and(terraform.as_ref()).map(|_| BTreeMap::new()));
+        let value = |tags: &Option<BTreeMap<String, (String, String)>>, key: &str| {
+            tags.as_ref()
+                .and_then(|x| x.get(key))
+                .map(|(_, value)| value.clone())
+        };
+        let discrepancy = |key: &str, kind| TagDiscrepancy {
+            key: bicep
+                .iter()
+                .chain(terraform.iter())
+                .find_map(|x| x.get(key))
+                .map_or_else(|| key.to_string(), |(key, _)| key.clone()),
+            kind,
+            bicep: value(&bicep, key),
+            terraform: value(&terraform, key),
+        };
+
+        let mut discrepancies = Vec::new();
+        if let (true, Some(bicep), Some(terraform)) = (self.compare, &bicep, &terraform) {
+            let keys: BTreeSet<&String> = bicep.keys().chain(terraform.keys()).collect();
+            for key in keys.into_iter().filter(|x| !unknown.contains(*x)) {
+                if bicep.get(key).map(|(_, x)| x) != terraform.get(key).map(|(_, x)| x) {
+                    discrepancies.push(discrepancy(key, TagDiscrepancyKind::Differs));
+                }
+            }
+        }
+        for key in self.required.iter().map(|x| x.to_lowercase()) {
+            let bicep_missing = bicep.as_ref().is_some_and(|x| !x.contains_key(&key));
+            let terraform_missing = terraform.as_ref().is_some_and(|x| !x.contains_key(&key) && !unknown.contains(&key));
+            if !self.is_ignored(&key) && (bicep_missing || terraform_missing) {
+                discrepancies.push(discrepancy(&key, TagDiscrepancyKind::Required));
+            }
+        }
+        let sides: Vec<&BTreeMap<String, (String, String)>> = bicep.iter().chain(terraform.iter()).collect();
+        for (key, allowed) in &self.allowed_values {
+            let key = key.to_lowercase();
+            let is_allowed = |tags: &&BTreeMap<String, (String, String)>| {
+                tags.get(&key).is_none_or(|(_, value)| allowed.contains(value))
+            };
+            if !self.is_ignored(&key) && !sides.iter().all(is_allowed) {
+                discrepancies.push(discrepancy(&key, TagDiscrepancyKind::NotAllowed));
+            }
+        }
+        discrepancies
+    }
+
+    fn is_ignored(&self, key: &str) -> bool {
+        self.ignore.iter().any(|x| x.eq_ignore_ascii_case(key))
+    }
+
+    /// The tags that are not ignored, keyed by lowercase key, with the key
+    /// as written and the value.
+    fn normalize(&self, tags: &BTreeMap<String, String>) -> BTreeMap<String, (String, String)> {
+        tags.iter()
+            .filter(|(key, _)| !self.is_ignored(key))
+            .map(|(key, value)| (key.to_lowercase(), (key.clone(), value.clone())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tags_test {
+    use super::*;
+
+    fn resource(tags: Option<&[(&str, &str)]>) -> ResourceResult {
+        ResourceResult {
+            resource_type: "key_vault".to_string(),
+            tags: tags.map(|x| x.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tag_policy() {
+        let policy: TagPolicy = serde_yaml::from_str(
+            r#"
+            required: [owner, environment]
+            allowedValues:
+              environment: [dev, prod]
+            "#,
+        )
+        .unwrap();
+        let bicep = resource(Some(&[("azd-env-name", "dev"), ("Owner", "platform"), ("environment", "test")]));
+        let terraform = resource(Some(&[("owner", "apps"), ("environment", "test")]));
+        let discrepancies = policy.check(Some(&bicep), Some(&terraform));
+        assert_eq!(
+            discrepancies,
+            vec![
+                TagDiscrepancy {
+                    key: "Owner".to_string(),
+                    kind: TagDiscrepancyKind::Differs,
+                    bicep: Some("platform".to_string()),
+                    terraform: Some("apps".to_string()),
+                },
+                TagDiscrepancy {
+                    key: "environment".to_string(),
+                    kind: TagDiscrepancyKind::NotAllowed,
+                    bicep: Some("test".to_string()),
+                    terraform: Some("test".to_string()),
+                },
+            ]
+        );
+
+        // What-if leaves out empty tags, and an inline child has none to check.
+        let discrepancies = policy.check(Some(&resource(None)), Some(&terraform));
+        let kinds: Vec<(TagDiscrepancyKind, &str)> = discrepancies.iter().map(|x| (x.kind, x.key.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (TagDiscrepancyKind::Differs, "environment"),
+                (TagDiscrepancyKind::Differs, "owner"),
+                (TagDiscrepancyKind::Required, "owner"),
+                (TagDiscrepancyKind::Required, "environment"),
+                (TagDiscrepancyKind::NotAllowed, "environment"),
+            ]
+        );
+        assert!(policy.check(Some(&resource(None)), None).is_empty());
+        assert!(TagPolicy::default().check(Some(&terraform), Some(&terraform)).is_empty());
+
+        // Tags are compared by default, leaving out azd-env-name, unless
+        // comparing is turned off.
+        let kinds: Vec<(TagDiscrepancyKind, String)> = TagPolicy::default()
+            .check(Some(&bicep), Some(&terraform))
+            .into_iter()
+            .map(|x| (x.kind, x.key))
+            .collect();
+        assert_eq!(kinds, vec![(TagDiscrepancyKind::Differs, "Owner".to_string())]);
+        let policy: TagPolicy = serde_yaml::from_str("compare: false").unwrap();
+        assert_eq!(policy.ignore, vec!["azd-env-name"]);
+        assert!(policy.check(Some(&bicep), Some(&terraform)).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_tags() {
+        let policy: TagPolicy = serde_yaml::from_str("required: [owner]").unwrap();
+        let bicep = resource(Some(&[("azd-env-name", "dev"), ("owner", "platform"), ("environment", "dev")]));
+        let mut terraform = resource(Some(&[("environment", "dev")]));
+        terraform.unknown_tags = vec!["owner".to_string()];
+        assert!(policy.check(Some(&bicep), Some(&terraform)).is_empty());
+
+        terraform.unknown_tags.clear();
+        let kinds: Vec<(TagDiscrepancyKind, String)> = policy
+            .check(Some(&bicep), Some(&terraform))
+            .into_iter()
+            .map(|x| (x.kind, x.key))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![(TagDiscrepancyKind::Differs, "owner".to_string()), (TagDiscrepancyKind::Required, "owner".to_string())]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+pub const LOCK_FILE: &str = ".terraform.lock.hcl";
+
+lazy_static! {
+    static ref PROVIDER_REGEX: Regex =
+        Regex::new(r#"(?m)^provider\s+"([^"]+)"\s*\{[^}]*?^\s*version\s*=\s*"([^"]+)""#).unwrap();
+}
+
+/// A required version for a tool or provider, keyed in `requiredVersions` by
+/// `az`, `bicep`, `terraform`, `tofu` or a provider, either by its short name
+/// (`azurerm`) or its full address.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct VersionConstraint {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exact: Option<String>,
+}
+
+impl VersionConstraint {
+    pub fn check(&self, tool: &str, version: &str) -> Result<()> {
+        let actual = parse_version(version)?;
+        if let Some(exact) = &self.exact {
+            if actual != parse_version(exact)? {
+                return Err(anyhow!("{} {} is not the required version {}.", tool, version, exact));
+            }
+        }
+        if let Some(minimum) = &self.minimum {
+            if actual < parse_version(minimum)? {
+                return Err(anyhow!("{} {} is older than the minimum version {}.", tool, version, minimum));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses versions like `v1.6`, `2.57.0` or `1.7.0-beta1`, filling in a
+/// missing minor or patch number.
+fn parse_version(version: &str) -> Result<Version> {
+    let version = version.trim().trim_start_matches('v');
+    let (core, pre_release) = match version.split_once('-') {
+        Some((core, pre_release)) => (core, Some(pre_release)),
+        None => (version, None),
+    };
+    let mut parts: Vec<&str> = core.split('.').collect();
+    parts.resize(3.max(parts.len()), "0");
+    let mut normalized = parts.join(".");
+    if let Some(pre_release) = pre_release {
+        normalized = format!("{}-{}", normalized, pre_release);
+    }
+    Version::parse(&normalized).map_err(|e| anyhow!("Unable to parse version '{}': {}", version, e))
+}
+
+/// Provider versions selected in the dependency lock file of a Terraform
+/// directory, keyed by provider address.
+pub fn read_provider_versions(terraform_path: &Path) -> Result<BTreeMap<String, String>> {
+    let contents = fs::read_to_string(terraform_path.join(LOCK_FILE))?;
+    Ok(PROVIDER_REGEX
+        .captures_iter(&contents)
+        .map(|x| (x[1].to_string(), x[2].to_string()))
+        .collect())
+}
+
+/// Finds the version a constraint key refers to: a tool name or provider
+/// address, or the last segment of a provider address.
+pub fn find_version<'a>(versions: &'a BTreeMap<String, String>, key: &str) -> Option<&'a String> {
+    versions.get(key).or_else(|| {
+        versions
+            .iter()
+            .find(|(name, _)| name.rsplit('/').next() == Some(key) && name.contains('/'))
+            .map(|(_, version)| version)
+    })
+}
+
+#[cfg(test)]
+mod versions_test {
+    use super::*;
+
+    #[test]
+    fn test_version_constraints() {
+        let minimum = VersionConstraint {
+            minimum: Some("2.50".to_string()),
+            exact: None,
+        };
+        assert!(minimum.check("az", "2.57.0").is_ok());
+        assert!(minimum.check("az", "2.49.1").is_err());
+
+        let exact = VersionConstraint {
+            minimum: None,
+            exact: Some("v1.6.5".to_string()),
+        };
+        assert!(exact.check("terraform", "1.6.5").is_ok());
+        assert!(exact.check("terraform", "1.7.0-beta1").is_err());
+    }
+
+    #[test]
+    fn test_read_lock_file_providers() {
+        let lock_file = r#"
+# This file is maintained automatically by "terraform init".
+# Manual edits may be lost in future updates.
+
+provider "registry.terraform.io/azure/azapi" {
+  version     = "1.12.1"
+  constraints = "~> 1.12"
+  hashes = [
+    "h1:EaasJJ4ow5ezS0NV7yXaxHGkD7XCkmsBCA1q4zKDhQM=",
+  ]
+}
+
+provider "registry.terraform.io/hashicorp/azurerm" {
+  version     = "3.90.0"
+  constraints = ">= 3.80.0"
+  hashes = [
+    "h1:4OPn8k++5ibl4Mh+qC5WrrXeu+y1ns6vyOmBeTGSOEs=",
+  ]
+}
+"#;
+        let directory = std::env::temp_dir().join(format!("infra-test-lock-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join(LOCK_FILE), lock_file).unwrap();
+        let versions = read_provider_versions(&directory).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(versions.len(), 2);
+        assert_eq!(find_version(&versions, "azurerm"), Some(&"3.90.0".to_string()));
+        assert_eq!(
+            find_version(&versions, "registry.terraform.io/azure/azapi"),
+            Some(&"1.12.1".to_string())
+        );
+        assert_eq!(find_version(&versions, "random"), None);
+    }
+}
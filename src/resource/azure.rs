@@ -1,26 +1,22 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use crate::output_tester::ResourceResult;
+use super::{CanonicalResource, ResourceId};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct AzureResourceChange {
     pub changes: Vec<AzureResourceChangeDetail>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum AzureResourceChangeType {
+    #[default]
     Create,
     Delete,
     Update,
     Unsupported,
 }
 
-impl Default for AzureResourceChangeType {
-    fn default() -> Self {
-        AzureResourceChangeType::Create
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct AzureResourceChangeDetail {
     pub after: Option<AzureResource>,
@@ -34,6 +30,39 @@ pub struct AzureResourceChangeDetail {
     pub unsupported_reason: Option<String>,
 }
 
+impl AzureResourceChangeDetail {
+    /// The resource this change deploys, or `None` for a deletion.  What-if
+    /// cannot evaluate some resources, such as Microsoft Graph ones from the
+    /// Bicep extension, and reports them as unsupported without an `after`
+    /// state, so their type and name come from the resource ID instead.
+    pub fn get_canonical_resource(&self) -> Option<CanonicalResource> {
+        let id = ResourceId::parse(&self.resource_id);
+        match &self.after {
+            Some(after) => Some(CanonicalResource {
+                parent: id.as_ref().and_then(ResourceId::get_parent),
+                id,
+                resource_type: after.resource_type.clone(),
+                name: after.name.clone(),
+                location: after.location.clone(),
+                tags: after.tags.clone().unwrap_or_default().into_iter().collect(),
+                sku: after.sku.clone(),
+                properties: after.properties.clone().unwrap_or_default(),
+            }),
+            None if self.change_type == AzureResourceChangeType::Unsupported => {
+                let id = id?;
+                Some(CanonicalResource {
+                    resource_type: id.get_resource_type(),
+                    name: id.get_name().to_string(),
+                    parent: id.get_parent(),
+                    id: Some(id),
+                    ..Default::default()
+                })
+            }
+            None => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct AzureResource {
     #[serde(rename = "apiVersion")]
@@ -44,34 +73,16 @@ pub struct AzureResource {
     tags: Option<HashMap<String, String>>,
     #[serde(rename = "type")]
     resource_type: String,
-}
-
-impl AzureResource {
-    pub fn get_comparison_resource(&self) -> ResourceResult {
-        let resource_type = match self.resource_type.as_str() {
-            "Microsoft.Resources/resourceGroups" => "resource_group".to_string(),
-            "Microsoft.ContainerService/managedClusters" => "kubernetes_cluster".to_string(),
-            "Microsoft.Network/virtualNetworks" => "virtual_network".to_string(),
-            "Microsoft.Network/publicIPAddresses" => "public_ip".to_string(),
-            "Microsoft.Network/networkInterfaces" => "network_interface".to_string(),
-            "Microsoft.Network/networkSecurityGroups" => "network_security_group".to_string(),
-            "Microsoft.KeyVault/vaults" => "key_vault".to_string(),
-            "Microsoft.Authorization/roleAssignments" => "role_assignment".to_string(),
-            _ => self.resource_type.to_string(),
-        };
-        ResourceResult {
-            resource_type: resource_type,
-            resource_name: Some(self.name.clone()),
-            provider: None,
-            is_expected: None,
-        }
-    }
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sku: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    properties: Option<Value>,
 }
 
 #[cfg(test)]
 mod azure_test {
     use super::*;
-    use serde_json;
+    use crate::resource::ResourceTypeMap;
 
     #[test]
     fn test_deserialize_azure_resource_change() {
@@ -116,13 +127,13 @@ mod azure_test {
         }
         "#;
         let changes: AzureResourceChange = serde_json::from_str(json).unwrap();
-        assert_eq!(*&changes.changes.len(), 2);
+        assert_eq!(changes.changes.len(), 2);
         assert_eq!(
             &changes.changes[0].resource_id,
             "/subscriptions/13ae0661-466f-4189-9095-cbd2e68a485f/resourceGroups/rg-nevermore"
         );
         assert_eq!(
-            *&changes.changes[0].change_type,
+            changes.changes[0].change_type,
             AzureResourceChangeType::Create
         );
         assert_eq!(
@@ -134,7 +145,7 @@ mod azure_test {
             "/subscriptions/13ae0661-466f-4189-9095-cbd2e68a485f/resourceGroups/rg-nevermore"
         );
         assert_eq!(
-            *&changes.changes[0]
+            changes.changes[0]
                 .after
                 .as_ref()
                 .unwrap()
@@ -148,7 +159,7 @@ mod azure_test {
             "rg-nevermore"
         );
         assert_eq!(
-            *&changes.changes[0]
+            changes.changes[0]
                 .after
                 .as_ref()
                 .unwrap()
@@ -164,4 +175,28 @@ mod azure_test {
             "Microsoft.Resources/resourceGroups"
         );
     }
+
+    #[test]
+    fn test_unsupported_graph_resource() {
+        let json = r#"
+        {
+            "after": null,
+            "before": null,
+            "changeType": "Unsupported",
+            "delta": null,
+            "resourceId": "/providers/Microsoft.Graph/applications/app-nevermore",
+            "unsupportedReason": "Changes to the resource declared at ... are not supported by what-if."
+        }
+        "#;
+        let change: AzureResourceChangeDetail = serde_json::from_str(json).unwrap();
+        let types = ResourceTypeMap::default();
+        let result = change.get_canonical_resource().unwrap().get_comparison_resource(&types).unwrap();
+        assert_eq!(result.resource_type, "azuread_application");
+        assert_eq!(result.resource_name.as_deref(), Some("app-nevermore"));
+
+        assert_eq!(
+            result.id.as_deref(),
+            Some("/providers/Microsoft.Graph/applications/app-nevermore")
+        );
+    }
 }
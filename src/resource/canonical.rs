@@ -0,0 +1,423 @@
+use super::{get_attribute_references, get_planned_values, strip_instance_key, ResourceId, ResourceTypeMap};
+use crate::output_tester::ResourceResult;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How azurerm resource types build their ARM resource IDs.  Placeholders
+/// name planned values, apart from `{subscription_id}`, which is the
+/// subscription the plan ran against.
+const TERRAFORM_ID_PATTERNS: &[(&str, &str)] = &[
+    ("azurerm_resource_group", "/subscriptions/{subscription_id}/resourceGroups/{name}"),
+    (
+        "azurerm_kubernetes_cluster",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.ContainerService/managedClusters/{name}",
+    ),
+    (
+        "azurerm_virtual_network",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.Network/virtualNetworks/{name}",
+    ),
+    (
+        "azurerm_subnet",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.Network/virtualNetworks/{virtual_network_name}/subnets/{name}",
+    ),
+    (
+        "azurerm_public_ip",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.Network/publicIPAddresses/{name}",
+    ),
+    (
+        "azurerm_network_interface",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.Network/networkInterfaces/{name}",
+    ),
+    (
+        "azurerm_network_security_group",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.Network/networkSecurityGroups/{name}",
+    ),
+    (
+        "azurerm_key_vault",
+        "/subscriptions/{subscription_id}/resourceGroups/{resource_group_name}/providers/Microsoft.KeyVault/vaults/{name}",
+    ),
+];
+
+/// azurerm child and extension resources, which name the resource they
+/// belong to by ID: the attribute holding that ID, and their ARM type.
+const TERRAFORM_SCOPED_TYPES: &[(&str, &str, &str)] = &[
+    (
+        "azurerm_kubernetes_cluster_node_pool",
+        "kubernetes_cluster_id",
+        "Microsoft.ContainerService/managedClusters/agentPools",
+    ),
+    ("azurerm_key_vault_secret", "key_vault_id", "Microsoft.KeyVault/vaults/secrets"),
+    (
+        "azurerm_monitor_diagnostic_setting",
+        "target_resource_id",
+        "Microsoft.Insights/diagnosticSettings",
+    ),
+    ("azurerm_role_assignment", "scope", "Microsoft.Authorization/roleAssignments"),
+];
+
+/// Child resources that can also be declared inline on their parent: the
+/// parent's ARM type, the ARM property or azurerm block that lists them, and
+/// the child type.
+const INLINE_CHILDREN: &[(&str, &[&str], &str)] = &[
+    ("Microsoft.Network/virtualNetworks", &["subnets", "subnet"], "subnets"),
+    (
+        "Microsoft.ContainerService/managedClusters",
+        &["agentPoolProfiles", "default_node_pool"],
+        "agentPools",
+    ),
+];
+
+/// A resource as either tool plans it, in ARM terms, so the two can be
+/// matched by resource ID rather than by type alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanonicalResource {
+    pub id: Option<ResourceId>,
+    /// The resource this is a child or extension of, or the scope it is
+    /// deployed to.  It can be known when the resource's own name is not.
+    pub parent: Option<ResourceId>,
+    pub resource_type: String,
+    pub name: String,
+    pub location: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub sku: Option<Value>,
+    pub properties: Value,
+}
+
+impl CanonicalResource {
+    /// The resource a Terraform resource plans, from its planned values.
+    /// The ID is known when the plan already has it, for an existing
+    /// resource, or when the values fill in the type's ID pattern.
+    pub fn from_terraform(
+        terraform_type: &str,
+        values: &Value,
+        subscription_id: Option<&str>,
+    ) -> CanonicalResource {
+        let id = values["id"]
+            .as_str()
+            .and_then(ResourceId::parse)
+            .or_else(|| build_terraform_id(terraform_type, values, subscription_id));
+        let resource_type = match &id {
+            Some(id) => id.get_resource_type(),
+            None => terraform_type.to_string(),
+        };
+        let tags = values["tags"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+            .collect();
+        let sku = ["sku", "sku_name", "sku_tier"]
+            .iter()
+            .find_map(|x| values.get(x).filter(|v| !v.is_null()).cloned());
+        CanonicalResource {
+            parent: id.as_ref().and_then(ResourceId::get_parent),
+            id,
+            resource_type,
+            name: values["name"].as_str().unwrap_or_default().to_string(),
+            location: values["location"].as_str().map(String::from),
+            tags,
+            sku,
+            properties: values.clone(),
+        }
+    }
+
+    /// The children declared inline on this resource, such as a virtual
+    /// network's subnets or a cluster's agent pools.
+    pub fn get_inline_children(&self) -> Vec<CanonicalResource> {
+        let Some((_, properties, child_type)) = INLINE_CHILDREN
+            .iter()
+            .find(|(parent_type, _, _)| parent_type.eq_ignore_ascii_case(&self.resource_type))
+        else {
+            return Vec::new();
+        };
+        properties
+            .iter()
+            .flat_map(|x| self.properties[x].as_array().into_iter().flatten())
+            .filter_map(|child| {
+                let name = child["name"].as_str()?;
+                Some(CanonicalResource {
+                    id: self.id.as_ref().and_then(|x| x.with_child(child_type, name)),
+                    parent: self.id.clone(),
+                    resource_type: format!("{}/{}", self.resource_type, child_type),
+                    name: name.to_string(),
+                    properties: child.clone(),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// The resource to compare, or `None` when a type mapping excludes it.
+    pub fn get_comparison_resource(&self, types: &ResourceTypeMap) -> Option<ResourceResult> {
+        Some(ResourceResult {
+            resource_type: types.get_comparison_type(&self.resource_type)?,
+            resource_name: Some(self.name.clone()),
+            id: self.id.as_ref().map(|x| x.to_string()),
+            parent: self.parent.as_ref().map(|x| x.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+/// The canonical resources for the Terraform resources at `addresses`, keyed
+/// by address.  Child and extension resources whose parent ID is unknown
+/// until apply are resolved through the configuration to the resource they
+/// refer to, so they are keyed under that resource's planned ID.
+pub fn get_terraform_canonical_resources(
+    structured_plan: Option<&Value>,
+    addresses: &[(&str, &str)],
+    subscription_id: Option<&str>,
+) -> HashMap<String, CanonicalResource> {
+    let planned_values = structured_plan.map(get_planned_values).unwrap_or_default();
+    let mut resources: HashMap<String, CanonicalResource> = addresses
+        .iter()
+        .map(|(address, terraform_type)| {
+            let values = planned_values.get(*address).unwrap_or(&Value::Null);
+            let resource = CanonicalResource::from_terraform(terraform_type, values, subscription_id);
+            (address.to_string(), resource)
+        })
+        .collect();
+
+    // Each pass can resolve resources scoped to ones the previous resolved.
+    let mut unresolved: HashSet<&str> = addresses
+        .iter()
+        .filter(|(address, _)| resources[*address].parent.is_none())
+        .map(|(address, _)| *address)
+        .collect();
+    loop {
+        let mut resolved = Vec::new();
+        for (address, terraform_type) in addresses.iter().filter(|(x, _)| unresolved.contains(x)) {
+            let Some((_, attribute, arm_type)) = TERRAFORM_SCOPED_TYPES.iter().find(|(x, _, _)| x == terraform_type)
+            else {
+                continue;
+            };
+            let values = planned_values.get(*address).unwrap_or(&Value::Null);
+            let parent = values[attribute].as_str().and_then(ResourceId::parse).or_else(|| {
+                let structured_plan = structured_plan?;
+                get_attribute_references(structured_plan, address, attribute)
+                    .iter()
+                    .find_map(|x| find_instance(&resources, x, address)?.id.clone())
+            });
+            if let Some(parent) = parent {
+                let resource = resources.get_mut(*address).unwrap();
+                resource.id = values["name"].as_str().and_then(|x| get_scoped_id(&parent, arm_type, x));
+                resource.resource_type = arm_type.to_string();
+                resource.parent = Some(parent);
+                resolved.push(*address);
+            }
+        }
+        if resolved.is_empty() {
+            return resources;
+        }
+        resolved.iter().for_each(|x| {
+            unresolved.remove(x);
+        });
+    }
+}
+
+/// The instance of the resource at `reference` that `address` most likely
+/// refers to: the resource itself, or the instance with the same key.
+fn find_instance<'a>(
+    resources: &'a HashMap<String, CanonicalResource>,
+    reference: &str,
+    address: &str,
+) -> Option<&'a CanonicalResource> {
+    if let Some(resource) = resources.get(reference) {
+        return Some(resource);
+    }
+    let key = &address[strip_instance_key(address).len()..];
+    resources.get(&format!("{}{}", reference, key)).or_else(|| {
+        resources
+            .iter()
+            .filter(|(x, _)| strip_instance_key(x) == reference)
+            .min_by_key(|(x, _)| x.as_str())
+            .map(|(_, resource)| resource)
+    })
+}
+
+/// The ID of a resource of `arm_type` named `name` under `parent`.  A child
+/// type, e.g. `Microsoft.Network/virtualNetworks/subnets`, extends its
+/// parent's type; any other type under a resource is an extension resource,
+/// such as a role assignment.
+fn get_scoped_id(parent: &ResourceId, arm_type: &str, name: &str) -> Option<ResourceId> {
+    if let Some((parent_type, child_type)) = arm_type.rsplit_once('/') {
+        if parent.get_namespace().is_some() && parent.get_resource_type().eq_ignore_ascii_case(parent_type) {
+            return parent.with_child(child_type, name);
+        }
+    }
+    let (namespace, resource_type) = arm_type.split_once('/')?;
+    Some(parent.with_provider(namespace, resource_type, name))
+}
+
+fn build_terraform_id(terraform_type: &str, values: &Value, subscription_id: Option<&str>) -> Option<ResourceId> {
+    if terraform_type == "azapi_resource" {
+        let (arm_type, _) = values["type"].as_str()?.split_once('@')?;
+        let parent = ResourceId::parse(values["parent_id"].as_str()?)?;
+        return get_scoped_id(&parent, arm_type, values["name"].as_str()?);
+    }
+
+    let (_, pattern) = TERRAFORM_ID_PATTERNS.iter().find(|(x, _)| *x == terraform_type)?;
+    let mut id = pattern.to_string();
+    while let Some(start) = id.find('{') {
+        let end = start + id[start..].find('}')?;
+        let key = &id[start + 1..end];
+        let value = match key {
+            "subscription_id" => subscription_id?.to_string(),
+            _ => values[key].as_str()?.to_string(),
+        };
+        id.replace_range(start..=end, &value);
+    }
+    ResourceId::parse(&id)
+}
+
+#[cfg(test)]
+mod canonical_test {
+    use super::*;
+    use crate::resource::AzureResourceChangeDetail;
+
+    const SUBSCRIPTION_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+    #[test]
+    fn test_whatif_and_terraform_ids_match() {
+        let change: AzureResourceChangeDetail = serde_json::from_str(
+            r#"{
+                "after": {
+                    "apiVersion": "2023-07-01",
+                    "id": "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.KeyVault/vaults/kv-test",
+                    "location": "eastus",
+                    "name": "kv-test",
+                    "tags": {"azd-env-name": "test"},
+                    "type": "Microsoft.KeyVault/vaults",
+                    "properties": {"sku": {"family": "A", "name": "standard"}}
+                },
+                "changeType": "Create",
+                "resourceId": "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.KeyVault/vaults/kv-test"
+            }"#,
+        )
+        .unwrap();
+        let bicep = change.get_canonical_resource().unwrap();
+        assert_eq!(bicep.resource_type, "Microsoft.KeyVault/vaults");
+        assert_eq!(bicep.tags["azd-env-name"], "test");
+
+        let values = serde_json::json!({
+            "name": "kv-test",
+            "resource_group_name": "RG-TEST",
+            "location": "eastus",
+            "sku_name": "standard",
+            "tags": {"azd-env-name": "test"}
+        });
+        let terraform = CanonicalResource::from_terraform("azurerm_key_vault", &values, Some(SUBSCRIPTION_ID));
+        assert_eq!(terraform.id, bicep.id);
+        assert_eq!(terraform.resource_type, "Microsoft.KeyVault/vaults");
+        assert_eq!(terraform.sku, Some(Value::from("standard")));
+        assert_eq!(terraform.tags, bicep.tags);
+
+        let terraform = CanonicalResource::from_terraform("azurerm_key_vault", &values, None);
+        assert_eq!(terraform.id, None);
+        assert_eq!(terraform.resource_type, "azurerm_key_vault");
+    }
+
+    #[test]
+    fn test_azapi_id_from_parent() {
+        let values = serde_json::json!({
+            "type": "Microsoft.App/containerApps@2023-05-01",
+            "name": "ca-test",
+            "parent_id": "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test"
+        });
+        let resource = CanonicalResource::from_terraform("azapi_resource", &values, None);
+        assert_eq!(
+            resource.id.unwrap().to_string(),
+            "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.App/containerApps/ca-test"
+        );
+
+        let values = serde_json::json!({
+            "type": "Microsoft.Network/virtualNetworks/subnets@2023-05-01",
+            "name": "app",
+            "parent_id": "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.Network/virtualNetworks/vnet-test"
+        });
+        let resource = CanonicalResource::from_terraform("azapi_resource", &values, None);
+        assert_eq!(resource.resource_type, "Microsoft.Network/virtualNetworks/subnets");
+        assert_eq!(resource.id.unwrap().get_name(), "app");
+    }
+
+    #[test]
+    fn test_inline_children() {
+        let change: AzureResourceChangeDetail = serde_json::from_str(
+            r#"{
+                "after": {
+                    "apiVersion": "2023-05-01",
+                    "id": "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.Network/virtualNetworks/vnet-test",
+                    "name": "vnet-test",
+                    "type": "Microsoft.Network/virtualNetworks",
+                    "properties": {"subnets": [{"name": "app", "properties": {"addressPrefix": "10.0.1.0/24"}}]}
+                },
+                "changeType": "Create",
+                "resourceId": "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.Network/virtualNetworks/vnet-test"
+            }"#,
+        )
+        .unwrap();
+        let bicep = change.get_canonical_resource().unwrap();
+        let children = bicep.get_inline_children();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].resource_type, "Microsoft.Network/virtualNetworks/subnets");
+        assert_eq!(children[0].parent, bicep.id);
+
+        let values = serde_json::json!({"name": "app", "resource_group_name": "rg-test", "virtual_network_name": "vnet-test"});
+        let terraform = CanonicalResource::from_terraform("azurerm_subnet", &values, Some(SUBSCRIPTION_ID));
+        assert_eq!(terraform.id, children[0].id);
+
+        let values = serde_json::json!({
+            "name": "aks-test",
+            "resource_group_name": "rg-test",
+            "default_node_pool": [{"name": "system", "vm_size": "Standard_D2s_v3"}]
+        });
+        let terraform = CanonicalResource::from_terraform("azurerm_kubernetes_cluster", &values, Some(SUBSCRIPTION_ID));
+        let children = terraform.get_inline_children();
+        assert_eq!(
+            children[0].id.as_ref().unwrap().to_string(),
+            "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.ContainerService/managedClusters/aks-test/agentPools/system"
+        );
+    }
+
+    #[test]
+    fn test_scoped_resources_resolved_through_configuration() {
+        let plan: Value = serde_json::from_str(
+            r#"{
+                "planned_values": {"root_module": {"resources": [
+                    {"address": "azurerm_kubernetes_cluster.aks", "values": {"name": "aks-test", "resource_group_name": "rg-test"}},
+                    {"address": "azurerm_kubernetes_cluster_node_pool.user", "values": {"name": "user"}},
+                    {"address": "azurerm_monitor_diagnostic_setting.aks", "values": {"name": "diag"}}
+                ]}},
+                "configuration": {"root_module": {"resources": [
+                    {"address": "azurerm_kubernetes_cluster_node_pool.user", "expressions": {
+                        "kubernetes_cluster_id": {"references": ["azurerm_kubernetes_cluster.aks.id", "azurerm_kubernetes_cluster.aks"]}
+                    }},
+                    {"address": "azurerm_monitor_diagnostic_setting.aks", "expressions": {
+                        "target_resource_id": {"references": ["azurerm_kubernetes_cluster.aks.id", "azurerm_kubernetes_cluster.aks"]}
+                    }}
+                ]}}
+            }"#,
+        )
+        .unwrap();
+        let resources = get_terraform_canonical_resources(
+            Some(&plan),
+            &[
+                ("azurerm_kubernetes_cluster.aks", "azurerm_kubernetes_cluster"),
+                ("azurerm_kubernetes_cluster_node_pool.user", "azurerm_kubernetes_cluster_node_pool"),
+                ("azurerm_monitor_diagnostic_setting.aks", "azurerm_monitor_diagnostic_setting"),
+            ],
+            Some(SUBSCRIPTION_ID),
+        );
+        let cluster = "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.ContainerService/managedClusters/aks-test";
+        let node_pool = &resources["azurerm_kubernetes_cluster_node_pool.user"];
+        assert_eq!(node_pool.resource_type, "Microsoft.ContainerService/managedClusters/agentPools");
+        assert_eq!(node_pool.id.as_ref().unwrap().to_string(), format!("{}/agentPools/user", cluster));
+
+        let diagnostic_setting = &resources["azurerm_monitor_diagnostic_setting.aks"];
+        assert_eq!(diagnostic_setting.parent.as_ref().unwrap().to_string(), cluster);
+        assert_eq!(
+            diagnostic_setting.id.as_ref().unwrap().to_string(),
+            format!("{}/providers/Microsoft.Insights/diagnosticSettings/diag", cluster)
+        );
+    }
+}
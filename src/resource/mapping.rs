@@ -0,0 +1,166 @@
+use crate::commands::AzureCloud;
+use std::collections::HashMap;
+
+/// ARM resource types and the short names expectations use for them, which
+/// are the azurerm resource names without the `azurerm_` prefix.  Microsoft
+/// Graph resources from the Bicep extension keep their full azuread names.
+const RESOURCE_TYPES: &[(&str, &str)] = &[
+    ("Microsoft.Resources/resourceGroups", "resource_group"),
+    ("Microsoft.ContainerService/managedClusters", "kubernetes_cluster"),
+    (
+        "Microsoft.ContainerService/managedClusters/agentPools",
+        "kubernetes_cluster_node_pool",
+    ),
+    ("Microsoft.Network/virtualNetworks", "virtual_network"),
+    ("Microsoft.Network/virtualNetworks/subnets", "subnet"),
+    ("Microsoft.Network/publicIPAddresses", "public_ip"),
+    ("Microsoft.Network/networkInterfaces", "network_interface"),
+    ("Microsoft.Network/networkSecurityGroups", "network_security_group"),
+    ("Microsoft.KeyVault/vaults", "key_vault"),
+    ("Microsoft.KeyVault/vaults/secrets", "key_vault_secret"),
+    ("Microsoft.Insights/diagnosticSettings", "monitor_diagnostic_setting"),
+    ("Microsoft.Authorization/roleAssignments", "role_assignment"),
+    ("Microsoft.Graph/applications", "azuread_application"),
+    (
+        "Microsoft.Graph/applications/federatedIdentityCredentials",
+        "azuread_application_federated_identity_credential",
+    ),
+    ("Microsoft.Graph/servicePrincipals", "azuread_service_principal"),
+    ("Microsoft.Graph/groups", "azuread_group"),
+    ("Microsoft.Graph/appRoleAssignedTo", "azuread_app_role_assignment"),
+    (
+        "Microsoft.Graph/oauth2PermissionGrants",
+        "azuread_service_principal_delegated_permission_grant",
+    ),
+];
+
+/// The ARM type prefixes a cloud cannot compare.  The Microsoft Graph Bicep
+/// extension is only offered in the public cloud, so what-if never plans
+/// Graph resources in a sovereign one.
+fn get_unsupported_types(cloud: AzureCloud) -> &'static [&'static str] {
+    match cloud {
+        AzureCloud::Public => &[],
+        AzureCloud::UsGovernment | AzureCloud::China => &["Microsoft.Graph/"],
+    }
+}
+
+/// Resolves ARM resource types to comparison names.  The cloud drops the
+/// types it cannot compare, and overrides come from the `typeMappings`
+/// config for anything else a cloud or project handles differently; an
+/// override with an empty name drops the type from the comparison.
+/// Overrides apply to both sides, but Terraform resources outside the
+/// built-in table only have an ARM type to look up once the plan reveals
+/// their resource ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceTypeMap {
+    overrides: HashMap<String, String>,
+}
+
+impl ResourceTypeMap {
+    pub fn new(cloud: AzureCloud, overrides: &HashMap<String, String>) -> Self {
+        let unsupported = get_unsupported_types(cloud);
+        let cloud_overrides = RESOURCE_TYPES
+            .iter()
+            .filter(|(arm_type, _)| unsupported.iter().any(|x| arm_type.starts_with(x)))
+            .map(|(arm_type, _)| (arm_type.to_string(), String::new()));
+        ResourceTypeMap {
+            // ARM resource types are case-insensitive.
+            overrides: cloud_overrides
+                .chain(overrides.iter().map(|(k, v)| (k.clone(), v.clone())))
+                .map(|(k, v)| (k.to_lowercase(), v))
+                .collect(),
+        }
+    }
+
+    /// The comparison name for a Terraform resource whose own name is
+    /// `name`, e.g. `key_vault` for `azurerm_key_vault`, and whose ARM type
+    /// is `arm_type` when the plan reveals it: the override for its ARM
+    /// type, or else `name`.  `None` when an override excludes it.
+    pub fn get_terraform_comparison_type(&self, name: &str, arm_type: Option<&str>) -> Option<String> {
+        let arm_type = RESOURCE_TYPES
+            .iter()
+            .find(|(_, x)| *x == name)
+            .map(|(arm_type, _)| *arm_type)
+            .or(arm_type);
+        match arm_type.and_then(|x| self.overrides.get(&x.to_lowercase())) {
+            Some(name) => (!name.is_empty()).then(|| name.clone()),
+            None => Some(name.to_string()),
+        }
+    }
+
+    /// The comparison name for `resource_type`, or `None` when an override
+    /// excludes it.  Unknown types compare by their ARM type.
+    pub fn get_comparison_type(&self, resource_type: &str) -> Option<String> {
+        if let Some(name) = self.overrides.get(&resource_type.to_lowercase()) {
+            return (!name.is_empty()).then(|| name.clone());
+        }
+        let name = RESOURCE_TYPES
+            .iter()
+            .find(|(arm_type, _)| arm_type.eq_ignore_ascii_case(resource_type))
+            .map_or(resource_type, |(_, name)| name);
+        Some(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod mapping_test {
+    use super::*;
+
+    #[test]
+    fn test_type_overrides() {
+        let overrides = HashMap::from([
+            ("Microsoft.Cdn/profiles".to_string(), String::new()),
+            ("microsoft.network/frontdoors".to_string(), "frontdoor".to_string()),
+        ]);
+        let types = ResourceTypeMap::new(AzureCloud::Public, &overrides);
+        assert_eq!(
+            types.get_comparison_type("Microsoft.KeyVault/Vaults"),
+            Some("key_vault".to_string())
+        );
+        assert_eq!(types.get_comparison_type("Microsoft.Cdn/profiles"), None);
+        assert_eq!(
+            types.get_comparison_type("Microsoft.Network/frontDoors"),
+            Some("frontdoor".to_string())
+        );
+        assert_eq!(
+            types.get_comparison_type("Microsoft.Graph/servicePrincipals"),
+            Some("azuread_service_principal".to_string())
+        );
+        assert_eq!(
+            types.get_comparison_type("Microsoft.Web/sites"),
+            Some("Microsoft.Web/sites".to_string())
+        );
+    }
+    #[test]
+    fn test_sovereign_cloud_types() {
+        let public = ResourceTypeMap::new(AzureCloud::Public, &HashMap::new());
+        assert_eq!(
+            public.get_terraform_comparison_type("azuread_application", None),
+            Some("azuread_application".to_string())
+        );
+
+        let overrides = HashMap::from([("Microsoft.Graph/groups".to_string(), "azuread_group".to_string())]);
+        let gov = ResourceTypeMap::new(AzureCloud::UsGovernment, &overrides);
+        assert_eq!(gov.get_comparison_type("Microsoft.Graph/applications"), None);
+        assert_eq!(gov.get_terraform_comparison_type("azuread_application", None), None);
+        assert_eq!(gov.get_terraform_comparison_type("azuread_group", None), Some("azuread_group".to_string()));
+        assert_eq!(gov.get_terraform_comparison_type("key_vault", None), Some("key_vault".to_string()));
+        assert_eq!(
+            gov.get_comparison_type("Microsoft.KeyVault/vaults"),
+            Some("key_vault".to_string())
+        );
+    }
+
+    #[test]
+    fn test_terraform_type_overrides() {
+        let overrides = HashMap::from([
+            ("Microsoft.KeyVault/vaults".to_string(), "vault".to_string()),
+            ("Microsoft.Cdn/profiles".to_string(), String::new()),
+        ]);
+        let types = ResourceTypeMap::new(AzureCloud::Public, &overrides);
+        assert_eq!(types.get_comparison_type("Microsoft.KeyVault/vaults"), Some("vault".to_string()));
+        assert_eq!(types.get_terraform_comparison_type("key_vault", None), Some("vault".to_string()));
+        assert_eq!(types.get_terraform_comparison_type("cdn_profile", Some("Microsoft.Cdn/profiles")), None);
+        assert_eq!(types.get_terraform_comparison_type("cdn_profile", None), Some("cdn_profile".to_string()));
+    }
+}
@@ -1,5 +1,15 @@
 mod azure;
+mod canonical;
+mod mapping;
+mod planned_values;
+mod resource_id;
 mod terraform;
+mod terraform_parser;
 
 pub use azure::*;
-pub use terraform::*;
\ No newline at end of file
+pub use canonical::*;
+pub use mapping::*;
+pub use planned_values::*;
+pub use resource_id::*;
+pub use terraform::*;
+pub use terraform_parser::*;
\ No newline at end of file
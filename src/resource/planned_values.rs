@@ -0,0 +1,177 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The values Terraform plans for each resource, keyed by address, from the
+/// `planned_values` of `terraform show -json`.  Values that are only known
+/// after apply are missing.
+pub fn get_planned_values(structured_plan: &Value) -> HashMap<String, Value> {
+    let mut values = HashMap::new();
+    collect_module_values(&structured_plan["planned_values"]["root_module"], &mut values);
+    values
+}
+
+fn collect_module_values(module: &Value, values: &mut HashMap<String, Value>) {
+    for resource in module["resources"].as_array().into_iter().flatten() {
+        if let Some(address) = resource["address"].as_str() {
+            values.insert(address.to_string(), resource["values"].clone());
+        }
+    }
+    for child in module["child_modules"].as_array().into_iter().flatten() {
+        collect_module_values(child, values);
+    }
+}
+
+/// The values Terraform only knows after apply, keyed by address, from the
+/// `after_unknown` of each resource change: `true` for an unknown value,
+/// or an object or array of them for one that is partly unknown.
+pub fn get_unknown_values(structured_plan: &Value) -> HashMap<String, Value> {
+    structured_plan["resource_changes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|x| Some((x["address"].as_str()?.to_string(), x["change"]["after_unknown"].clone())))
+        .collect()
+}
+
+/// The addresses an attribute of the resource at `address` refers to in
+/// the configuration, e.g. `azurerm_kubernetes_cluster.aks` for a node
+/// pool's `kubernetes_cluster_id`.  Attribute and module output references
+/// are trimmed to the resource they name, and made absolute.
+pub fn get_attribute_references(structured_plan: &Value, address: &str, attribute: &str) -> Vec<String> {
+    let (module_path, resource) = split_module_address(address);
+    let mut module = &structured_plan["configuration"]["root_module"];
+    for call in module_path.iter() {
+        module = &module["module_calls"][strip_instance_key(call.trim_start_matches("module."))]["module"];
+    }
+    let resource = strip_instance_key(resource);
+    let Some(configured) = module["resources"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|x| x["address"].as_str() == Some(resource))
+    else {
+        return Vec::new();
+    };
+    let prefix: String = module_path.iter().map(|x| format!("{}.", x)).collect();
+    configured["expressions"][attribute]["references"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.as_str())
+        .filter_map(|x| {
+            let parts: Vec<&str> = x.splitn(3, '.').collect();
+            match parts.as_slice() {
+                ["var", ..] | ["local", ..] | ["each", ..] | ["count", ..] | ["path", ..] => None,
+                ["module", ..] => None,
+                ["data", data_type, rest] => Some(format!("data.{}.{}", data_type, rest.split('.').next()?)),
+                [resource_type, name, ..] => Some(format!("{}.{}", resource_type, name)),
+                _ => None,
+            }
+        })
+        .map(|x| format!("{}{}", prefix, x))
+        .fold(Vec::new(), |mut references, x| {
+            if !references.contains(&x) {
+                references.push(x);
+            }
+            references
+        })
+}
+
+/// Splits an address into its module calls, e.g. `module.app["a"]`, and the
+/// resource within the module.
+pub fn split_module_address(address: &str) -> (Vec<&str>, &str) {
+    let mut modules = Vec::new();
+    let mut rest = address;
+    while rest.starts_with("module.") {
+        let Some(end) = find_unbracketed_dot(rest, "module.".len()) else {
+            break;
+        };
+        modules.push(&rest[..end]);
+        rest = &rest[end + 1..];
+    }
+    (modules, rest)
+}
+
+fn find_unbracketed_dot(address: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in address.char_indices().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            '.' if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The address without its `count` index or `for_each` key.
+pub fn strip_instance_key(address: &str) -> &str {
+    match address.strip_suffix(']').and_then(|x| x.rfind('[')) {
+        Some(index) => &address[..index],
+        None => address,
+    }
+}
+
+#[cfg(test)]
+mod planned_values_test {
+    use super::*;
+
+    #[test]
+    fn test_planned_values_from_child_modules() {
+        let plan: Value = serde_json::from_str(
+            r#"{"planned_values": {"root_module": {
+                "resources": [{"address": "azurerm_resource_group.rg", "values": {"name": "rg-test"}}],
+                "child_modules": [{"address": "module.app", "resources": [], "child_modules": [
+                    {"address": "module.app.module.api", "resources": [
+                        {"address": "module.app.module.api.azapi_resource.api", "values": {"type": "Microsoft.App/containerApps@2023-05-01"}}
+                    ]}
+                ]}]
+            }}}"#,
+        )
+        .unwrap();
+        let values = get_planned_values(&plan);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["azurerm_resource_group.rg"]["name"], "rg-test");
+        assert_eq!(
+            values["module.app.module.api.azapi_resource.api"]["type"],
+            "Microsoft.App/containerApps@2023-05-01"
+        );
+        assert!(get_planned_values(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_values() {
+        let plan = serde_json::json!({"resource_changes": [
+            {"address": "azurerm_key_vault.kv", "change": {"after_unknown": {"id": true, "tags": {"owner": true}}}}
+        ]});
+        let values = get_unknown_values(&plan);
+        assert_eq!(values["azurerm_key_vault.kv"]["tags"]["owner"], true);
+        assert!(get_unknown_values(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn test_attribute_references() {
+        let plan: Value = serde_json::from_str(
+            r#"{"configuration": {"root_module": {"module_calls": {"aks": {"module": {"resources": [
+                {"address": "azurerm_kubernetes_cluster_node_pool.user", "expressions": {
+                    "kubernetes_cluster_id": {"references": ["azurerm_kubernetes_cluster.aks.id", "azurerm_kubernetes_cluster.aks"]},
+                    "vnet_subnet_id": {"references": ["var.subnet_id"]}
+                }}
+            ]}}}}}}"#,
+        )
+        .unwrap();
+        let address = r#"module.aks["east"].azurerm_kubernetes_cluster_node_pool.user[0]"#;
+        assert_eq!(
+            get_attribute_references(&plan, address, "kubernetes_cluster_id"),
+            vec![r#"module.aks["east"].azurerm_kubernetes_cluster.aks"#]
+        );
+        assert!(get_attribute_references(&plan, address, "vnet_subnet_id").is_empty());
+        assert!(get_attribute_references(&plan, "azurerm_subnet.app", "virtual_network_name").is_empty());
+        assert_eq!(
+            split_module_address(r#"module.a["x.y"].module.b.azurerm_subnet.this["app"]"#),
+            (vec![r#"module.a["x.y"]"#, "module.b"], r#"azurerm_subnet.this["app"]"#)
+        );
+        assert_eq!(strip_instance_key(r#"azurerm_subnet.this["app"]"#), "azurerm_subnet.this");
+    }
+}
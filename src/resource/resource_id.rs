@@ -0,0 +1,403 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+#[cfg(test)]
+const MANAGEMENT_GROUP_NAMESPACE: &str = "Microsoft.Management";
+#[cfg(test)]
+const MANAGEMENT_GROUP_TYPE: &str = "managementGroups";
+
+/// The deployment scope a resource lives at.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScopeLevel {
+    Tenant,
+    ManagementGroup,
+    Subscription,
+    ResourceGroup,
+}
+
+/// One `/providers/{namespace}/{type}/{name}[/{type}/{name}...]` part of a
+/// resource ID.  A resource ID with several of them is an extension
+/// resource, scoped to the resource the earlier parts name.
+#[derive(Debug, Clone, Default)]
+struct ProviderSegment {
+    namespace: String,
+    types: Vec<String>,
+    names: Vec<String>,
+}
+
+/// A parsed ARM resource ID, e.g.
+/// `/subscriptions/{id}/resourceGroups/{rg}/providers/Microsoft.Network/virtualNetworks/{vnet}/subnets/{subnet}`.
+/// Tenant, management group, subscription and resource group level IDs are
+/// supported, with nested child types and extension resources such as
+/// `{vault id}/providers/Microsoft.Authorization/roleAssignments/{guid}`.
+/// ARM IDs are case-insensitive, and so is equality.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceId {
+    subscription_id: Option<String>,
+    resource_group: Option<String>,
+    providers: Vec<ProviderSegment>,
+}
+
+impl ResourceId {
+    pub fn parse(id: &str) -> Option<ResourceId> {
+        let segments: Vec<&str> = id.split('/').filter(|x| !x.is_empty()).collect();
+        let mut result = ResourceId::default();
+        let mut rest = segments.as_slice();
+        if let [subscriptions, subscription_id, tail @ ..] = rest {
+            if subscriptions.eq_ignore_ascii_case("subscriptions") {
+                result.subscription_id = Some(subscription_id.to_string());
+                rest = tail;
+                if let [resource_groups, resource_group, tail @ ..] = rest {
+                    if resource_groups.eq_ignore_ascii_case("resourceGroups") {
+                        result.resource_group = Some(resource_group.to_string());
+                        rest = tail;
+                    }
+                }
+            }
+        }
+        while let [providers, namespace, tail @ ..] = rest {
+            if !providers.eq_ignore_ascii_case("providers") {
+                return None;
+            }
+            let mut segment = ProviderSegment {
+                namespace: namespace.to_string(),
+                ..Default::default()
+            };
+            rest = tail;
+            while let [resource_type, name, tail @ ..] = rest {
+                if resource_type.eq_ignore_ascii_case("providers") {
+                    break;
+                }
+                segment.types.push(resource_type.to_string());
+                segment.names.push(name.to_string());
+                rest = tail;
+            }
+            if segment.types.is_empty() {
+                return None;
+            }
+            result.providers.push(segment);
+        }
+        let scoped = result.subscription_id.is_some() || !result.providers.is_empty();
+        (rest.is_empty() && scoped).then_some(result)
+    }
+
+    /// The root of the tenant, which tenant level resources are under.
+    #[cfg(test)]
+    pub fn tenant() -> ResourceId {
+        ResourceId::default()
+    }
+
+    #[cfg(test)]
+    pub fn management_group(name: &str) -> ResourceId {
+        ResourceId::tenant().with_provider(MANAGEMENT_GROUP_NAMESPACE, MANAGEMENT_GROUP_TYPE, name)
+    }
+
+    #[cfg(test)]
+    pub fn subscription(subscription_id: &str) -> ResourceId {
+        ResourceId {
+            subscription_id: Some(subscription_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(test)]
+    pub fn resource_group(subscription_id: &str, resource_group: &str) -> ResourceId {
+        ResourceId {
+            resource_group: Some(resource_group.to_string()),
+            ..ResourceId::subscription(subscription_id)
+        }
+    }
+
+    /// A resource of `namespace/resource_type` under this ID: a top level
+    /// resource when this is a scope, or an extension resource when this is
+    /// a resource.
+    pub fn with_provider(&self, namespace: &str, resource_type: &str, name: &str) -> ResourceId {
+        let mut result = self.clone();
+        result.providers.push(ProviderSegment {
+            namespace: namespace.to_string(),
+            types: vec![resource_type.to_string()],
+            names: vec![name.to_string()],
+        });
+        result
+    }
+
+    /// A child resource of this one, e.g. `subnets` of a virtual network.
+    /// Scopes have no children, so for them this is `None`.
+    pub fn with_child(&self, resource_type: &str, name: &str) -> Option<ResourceId> {
+        let mut result = self.clone();
+        let segment = result.providers.last_mut()?;
+        segment.types.push(resource_type.to_string());
+        segment.names.push(name.to_string());
+        Some(result)
+    }
+
+    /// The subscription, including the one an extension resource's scope
+    /// is in.
+    pub fn get_subscription_id(&self) -> Option<&str> {
+        self.subscription_id.as_deref()
+    }
+
+    #[cfg(test)]
+    pub fn get_resource_group(&self) -> Option<&str> {
+        self.resource_group.as_deref()
+    }
+
+    /// The management group this ID is, or is under.
+    #[cfg(test)]
+    pub fn get_management_group(&self) -> Option<&str> {
+        let segment = self.providers.first()?;
+        (self.subscription_id.is_none()
+            && segment.namespace.eq_ignore_ascii_case(MANAGEMENT_GROUP_NAMESPACE)
+            && segment.types[0].eq_ignore_ascii_case(MANAGEMENT_GROUP_TYPE))
+        .then(|| segment.names[0].as_str())
+    }
+
+    #[cfg(test)]
+    pub fn get_scope_level(&self) -> ScopeLevel {
+        if self.resource_group.is_some() {
+            ScopeLevel::ResourceGroup
+        } else if self.subscription_id.is_some() {
+            ScopeLevel::Subscription
+        } else if self.get_management_group().is_some() && !self.is_management_group() {
+            ScopeLevel::ManagementGroup
+        } else {
+            ScopeLevel::Tenant
+        }
+    }
+
+    #[cfg(test)]
+    fn is_management_group(&self) -> bool {
+        self.providers.len() == 1 && self.providers[0].types.len() == 1 && self.get_management_group().is_some()
+    }
+
+    /// The namespace of the resource type, e.g. `Microsoft.Network`.
+    pub fn get_namespace(&self) -> Option<&str> {
+        self.providers.last().map(|x| x.namespace.as_str())
+    }
+
+    /// The full ARM type, e.g. `Microsoft.Network/virtualNetworks/subnets`.
+    pub fn get_resource_type(&self) -> String {
+        match self.providers.last() {
+            Some(segment) => format!("{}/{}", segment.namespace, segment.types.join("/")),
+            None if self.resource_group.is_some() => "Microsoft.Resources/resourceGroups".to_string(),
+            None if self.subscription_id.is_some() => "Microsoft.Resources/subscriptions".to_string(),
+            None => "Microsoft.Resources/tenants".to_string(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.providers
+            .last()
+            .and_then(|x| x.names.last())
+            .or(self.resource_group.as_ref())
+            .or(self.subscription_id.as_ref())
+            .map_or("", |x| x.as_str())
+    }
+
+    /// True for a resource scoped to another resource, such as a role
+    /// assignment or diagnostic setting on a resource.
+    #[cfg(test)]
+    pub fn is_extension(&self) -> bool {
+        self.get_extension_scope().is_some()
+    }
+
+    /// The resource an extension resource is scoped to.
+    #[cfg(test)]
+    pub fn get_extension_scope(&self) -> Option<ResourceId> {
+        if self.providers.len() < 2 || self.is_management_group_scoped_resource() {
+            return None;
+        }
+        Some(self.truncated(self.providers.len() - 1))
+    }
+
+    // Resources deployed to a management group are extensions of it in
+    // the ID, but ARM treats them as management group level resources.
+    #[cfg(test)]
+    fn is_management_group_scoped_resource(&self) -> bool {
+        self.providers.len() == 2 && self.providers[0].types.len() == 1 && self.get_management_group().is_some()
+    }
+
+    /// The resource this one is a child of, or else the scope it is
+    /// deployed to: the resource group, subscription, management group,
+    /// tenant, or the resource an extension is scoped to.
+    pub fn get_parent(&self) -> Option<ResourceId> {
+        let segment = self.providers.last()?;
+        if segment.types.len() > 1 {
+            let mut parent = self.clone();
+            let last = parent.providers.last_mut().unwrap();
+            last.types.pop();
+            last.names.pop();
+            return Some(parent);
+        }
+        Some(self.truncated(self.providers.len() - 1))
+    }
+
+    fn truncated(&self, providers: usize) -> ResourceId {
+        ResourceId {
+            providers: self.providers[..providers].to_vec(),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for ResourceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(subscription_id) = &self.subscription_id {
+            write!(f, "/subscriptions/{}", subscription_id)?;
+        }
+        if let Some(resource_group) = &self.resource_group {
+            write!(f, "/resourceGroups/{}", resource_group)?;
+        }
+        for segment in &self.providers {
+            write!(f, "/providers/{}", segment.namespace)?;
+            for (resource_type, name) in segment.types.iter().zip(&segment.names) {
+                write!(f, "/{}/{}", resource_type, name)?;
+            }
+        }
+        if self.subscription_id.is_none() && self.providers.is_empty() {
+            write!(f, "/")?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for ResourceId {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string().eq_ignore_ascii_case(&other.to_string())
+    }
+}
+
+impl Eq for ResourceId {}
+
+impl Hash for ResourceId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_string().to_lowercase().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod resource_id_test {
+    use super::*;
+
+    const SUBSCRIPTION_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+    #[test]
+    fn test_parse_resource_ids() {
+        let id = ResourceId::parse(
+            "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.Network/virtualNetworks/vnet-test/subnets/app",
+        )
+        .unwrap();
+        assert_eq!(id.get_subscription_id(), Some(SUBSCRIPTION_ID));
+        assert_eq!(id.get_resource_group(), Some("rg-test"));
+        assert_eq!(id.get_namespace(), Some("Microsoft.Network"));
+        assert_eq!(id.get_resource_type(), "Microsoft.Network/virtualNetworks/subnets");
+        assert_eq!(id.get_name(), "app");
+        assert_eq!(id.get_scope_level(), ScopeLevel::ResourceGroup);
+        assert!(!id.is_extension());
+        assert_eq!(
+            id.get_parent().unwrap().to_string(),
+            "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test/providers/Microsoft.Network/virtualNetworks/vnet-test"
+        );
+
+        let id = ResourceId::parse("/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test").unwrap();
+        assert_eq!(id.get_resource_type(), "Microsoft.Resources/resourceGroups");
+        assert_eq!(id.get_name(), "rg-test");
+        assert_eq!(id.get_parent(), None);
+        assert_eq!(
+            id,
+            ResourceId::parse("/SUBSCRIPTIONS/00000000-0000-0000-0000-000000000000/resourcegroups/RG-TEST/").unwrap()
+        );
+        assert_eq!(id, ResourceId::resource_group(SUBSCRIPTION_ID, "rg-test"));
+        assert_eq!(
+            id.to_string(),
+            "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/rg-test"
+        );
+
+        let id = ResourceId::parse("/providers/Microsoft.Graph/applications/app-test").unwrap();
+        assert_eq!(id.get_resource_type(), "Microsoft.Graph/applications");
+        assert_eq!(id.get_subscription_id(), None);
+        assert_eq!(id.get_scope_level(), ScopeLevel::Tenant);
+        assert_eq!(id.get_parent(), Some(ResourceId::tenant()));
+        assert_eq!(ResourceId::tenant().to_string(), "/");
+
+        assert!(ResourceId::parse("").is_none());
+        assert!(ResourceId::parse("not-an-id").is_none());
+        assert!(ResourceId::parse("/subscriptions/x/resourceGroups/rg/providers/Microsoft.KeyVault/vaults").is_none());
+        assert!(
+            ResourceId::parse("/subscriptions/x/resourceGroups/rg/providers/Microsoft.KeyVault/vaults/kv/providers")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_management_group_ids() {
+        let group = ResourceId::parse("/providers/Microsoft.Management/managementGroups/mg-test").unwrap();
+        assert_eq!(group, ResourceId::management_group("mg-test"));
+        assert_eq!(group.get_management_group(), Some("mg-test"));
+        assert_eq!(group.get_scope_level(), ScopeLevel::Tenant);
+
+        let id = ResourceId::parse(
+            "/providers/Microsoft.Management/managementGroups/mg-test/providers/Microsoft.Authorization/policyDefinitions/require-tags",
+        )
+        .unwrap();
+        assert_eq!(id.get_management_group(), Some("mg-test"));
+        assert_eq!(id.get_scope_level(), ScopeLevel::ManagementGroup);
+        assert_eq!(id.get_resource_type(), "Microsoft.Authorization/policyDefinitions");
+        assert!(!id.is_extension());
+        assert_eq!(id.get_parent(), Some(group.clone()));
+        assert_eq!(
+            id,
+            group.with_provider("Microsoft.Authorization", "policyDefinitions", "require-tags")
+        );
+    }
+
+    #[test]
+    fn test_extension_resource_ids() {
+        let vault = ResourceId::resource_group(SUBSCRIPTION_ID, "rg-test").with_provider(
+            "Microsoft.KeyVault",
+            "vaults",
+            "kv-test",
+        );
+        let text = format!(
+            "{}/providers/Microsoft.Authorization/roleAssignments/11111111-1111-1111-1111-111111111111",
+            vault
+        );
+        let id = ResourceId::parse(&text).unwrap();
+        assert_eq!(id.to_string(), text);
+        assert!(id.is_extension());
+        assert_eq!(id.get_extension_scope(), Some(vault.clone()));
+        assert_eq!(id.get_parent(), Some(vault.clone()));
+        assert_eq!(id.get_resource_type(), "Microsoft.Authorization/roleAssignments");
+        assert_eq!(id.get_subscription_id(), Some(SUBSCRIPTION_ID));
+        assert_eq!(id.get_scope_level(), ScopeLevel::ResourceGroup);
+        assert_eq!(
+            id,
+            vault.with_provider(
+                "Microsoft.Authorization",
+                "roleAssignments",
+                "11111111-1111-1111-1111-111111111111"
+            )
+        );
+
+        let subscription_assignment = ResourceId::parse(
+            "/subscriptions/00000000-0000-0000-0000-000000000000/providers/Microsoft.Authorization/roleAssignments/22222222-2222-2222-2222-222222222222",
+        )
+        .unwrap();
+        assert!(!subscription_assignment.is_extension());
+        assert_eq!(
+            subscription_assignment.get_parent(),
+            Some(ResourceId::subscription(SUBSCRIPTION_ID))
+        );
+
+        let secret = vault.with_child("secrets", "password").unwrap();
+        assert_eq!(secret.get_resource_type(), "Microsoft.KeyVault/vaults/secrets");
+        assert!(ResourceId::subscription(SUBSCRIPTION_ID).with_child("secrets", "x").is_none());
+
+        let mut ids = std::collections::HashSet::new();
+        ids.insert(ResourceId::parse(&text.to_uppercase()).unwrap());
+        assert!(ids.contains(&id));
+    }
+}
@@ -1,9 +1,24 @@
-use serde::{Deserialize, Serialize};
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
 
+use super::ResourceTypeMap;
 use crate::output_tester::ResourceResult;
 
+/// Providers whose resources are Azure resources with a Bicep equivalent.
+const AZURE_PROVIDERS: [&str; 3] = ["azurerm", "azapi", "azuread"];
 
+/// What kind of resource a Terraform address refers to.  Only `azure`
+/// resources have a counterpart in the Bicep WhatIf output; `helper`
+/// resources such as `random_string` or `time_sleep` live only in Terraform.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceClass {
+    Azure,
+    Helper,
+    Data,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct TerraformResourceChange {
@@ -13,20 +28,48 @@ pub struct TerraformResourceChange {
     pub planned_change: Vec<TerraformPlanStep>,
     pub change_summary: Option<TerraformPlanStep>,
     pub outputs: Option<TerraformPlanStep>,
+    #[serde(default)]
+    pub diagnostics: Vec<TerraformPlanStep>,
+    #[serde(default)]
+    pub resource_drift: Vec<TerraformPlanStep>,
+    #[serde(default)]
+    pub refresh_complete: Vec<TerraformPlanStep>,
+    #[serde(default)]
+    pub apply_errored: Vec<TerraformPlanStep>,
+    /// Every other documented event: logs, progress, provisioner and test
+    /// events.
+    #[serde(default)]
+    pub other: Vec<TerraformPlanStep>,
+    /// Lines of the stream that were not Terraform events.
+    #[serde(default)]
+    pub malformed_lines: Vec<MalformedLine>,
+}
+
+/// A line of the event stream that could not be parsed, such as a provider
+/// panic or a wrapper's banner.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct MalformedLine {
+    pub line_number: usize,
+    pub content: String,
+    pub error: String,
 }
 
+/// One event of the `-json` machine readable UI.  Only `type` is required,
+/// so events from other Terraform versions and wrappers still parse.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct TerraformPlanStep {
     #[serde(rename = "type")]
     pub record_type: String,
-    #[serde(rename = "@level")]
+    #[serde(rename = "@level", default)]
     pub level: String,
-    #[serde(rename = "@message")]
+    #[serde(rename = "@message", default)]
     pub message: String,
-    #[serde(rename = "@module")]
+    #[serde(rename = "@module", default)]
     pub module: String,
-    #[serde(rename = "@timestamp")]
+    #[serde(rename = "@timestamp", default)]
     pub timestamp: String,
+    /// The CLI version on `version` events, which OpenTofu reports as `tofu`.
+    #[serde(alias = "tofu")]
     pub terraform: Option<String>,
     pub ui: Option<String>,
     pub hook: Option<TerraformHook>,
@@ -36,11 +79,38 @@ pub struct TerraformPlanStep {
     pub change: Option<TerraformHook>,
     pub changes: Option<TerraformChanges>,
     pub outputs: Option<HashMap<String, TerraformOutput>>,
+    pub diagnostic: Option<TerraformDiagnostic>,
+}
+
+/// The body of a `diagnostic` event: an error or warning from Terraform or
+/// a provider, optionally pointing at a source range.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TerraformDiagnostic {
+    pub severity: String,
+    pub summary: String,
+    #[serde(default)]
+    pub detail: String,
+    pub address: Option<String>,
+    pub range: Option<TerraformSourceRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TerraformSourceRange {
+    pub filename: String,
+    pub start: TerraformSourcePosition,
+    pub end: TerraformSourcePosition,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TerraformSourcePosition {
+    pub line: u32,
+    pub column: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct TerraformHook {
     pub resource: TerraformResource,
+    #[serde(default)]
     pub action: String,
     pub id_key: Option<String>,
     pub id_value: Option<String>,
@@ -55,33 +125,107 @@ pub struct TerraformResource {
     implied_provider: String,
     resource_type: String,
     resource_name: String,
+    #[serde(default, deserialize_with = "deserialize_resource_key")]
     resource_key: Option<String>,
 }
 
+/// `count` keys are numbers and `for_each` keys are strings.
+fn deserialize_resource_key<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => None,
+        Some(Value::String(key)) => Some(key),
+        Some(key) => Some(key.to_string()),
+    })
+}
+
 impl TerraformResource {
-    pub fn get_comparison_resource(&self) -> ResourceResult {
-        let resource_type = str::replace(&self.resource_type, "azurerm_", "");
-        ResourceResult {
-            resource_type: resource_type,
-            resource_name: Some(self.resource_name.clone()),
-            provider: None,
-            is_expected: None,
+    pub fn get_address(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn get_resource_type(&self) -> &str {
+        &self.resource_type
+    }
+
+    pub fn get_class(&self) -> ResourceClass {
+        if self.resource.starts_with("data.") {
+            ResourceClass::Data
+        } else if AZURE_PROVIDERS.contains(&self.implied_provider.as_str()) {
+            ResourceClass::Azure
+        } else {
+            ResourceClass::Helper
         }
     }
+
+    /// The resource to compare, or `None` when a type mapping excludes it.
+    /// `planned_values` are this resource's values from the structured plan,
+    /// which hold the ARM type of an `azapi_resource`, and `arm_type` is the
+    /// ARM type of its resource ID when the plan reveals it.
+    pub fn get_comparison_resource(
+        &self,
+        planned_values: Option<&Value>,
+        arm_type: Option<&str>,
+        types: &ResourceTypeMap,
+    ) -> Option<ResourceResult> {
+        let mut api_version = None;
+        let resource_type = if self.resource_type == "azapi_resource" {
+            match planned_values.and_then(|x| x["type"].as_str()) {
+                Some(azapi_type) => {
+                    let (arm_type, version) = split_azapi_type(azapi_type);
+                    api_version = version;
+                    types.get_comparison_type(arm_type)?
+                }
+                None => {
+                    warn!("No ARM type planned for {}. Comparing it as azapi_resource.", self.addr);
+                    self.resource_type.clone()
+                }
+            }
+        } else {
+            let resource_type = str::replace(&self.resource_type, "azurerm_", "");
+            types.get_terraform_comparison_type(&resource_type, arm_type)?
+        };
+        Some(ResourceResult {
+            resource_type,
+            resource_name: Some(self.resource_name.clone()),
+            address: Some(self.addr.clone()),
+            module: Some(self.module.clone()).filter(|x| !x.is_empty()),
+            api_version,
+            ..Default::default()
+        })
+    }
+}
+
+/// Splits an azapi `type`, e.g. `Microsoft.App/containerApps@2023-05-01`,
+/// into the ARM resource type and API version.
+fn split_azapi_type(azapi_type: &str) -> (&str, Option<String>) {
+    match azapi_type.split_once('@') {
+        Some((arm_type, api_version)) => (arm_type, Some(api_version.to_string())),
+        None => (azapi_type, None),
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct TerraformChanges {
+    #[serde(default)]
     add: u32,
+    #[serde(default)]
     change: u32,
+    #[serde(default)]
     import: u32,
+    #[serde(default)]
     remove: u32,
+    #[serde(default)]
     operation: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct TerraformOutput {
+    #[serde(default)]
     sensitive: bool,
+    #[serde(default)]
     action: String,
 }
 
@@ -89,7 +233,7 @@ pub struct TerraformOutput {
 #[cfg(test)]
 mod terraform_test {
     use super::*;
-    use serde_json;
+    use crate::commands::AzureCloud;
 
     #[test]
     fn test_deserialize_terraform_version() {
@@ -114,6 +258,97 @@ mod terraform_test {
         assert_eq!(version.ui.unwrap(), "1.2");
     }
 
+    #[test]
+    fn test_deserialize_terraform_diagnostic() {
+        let json = r#"
+        {
+            "@level": "warning",
+            "@message": "Warning: Argument is deprecated",
+            "@module": "terraform.ui",
+            "@timestamp": "2024-02-23T13:50:04.652705-06:00",
+            "diagnostic": {
+                "severity": "warning",
+                "summary": "Argument is deprecated",
+                "detail": "The `enable_rbac_authorization` property has been superseded.",
+                "address": "azurerm_key_vault.kv",
+                "range": {
+                    "filename": "main.tf",
+                    "start": {"line": 41, "column": 3, "byte": 1020},
+                    "end": {"line": 41, "column": 30, "byte": 1047}
+                },
+                "snippet": {
+                    "context": "resource \"azurerm_key_vault\" \"kv\"",
+                    "code": "  enable_rbac_authorization = true",
+                    "start_line": 41,
+                    "highlight_start_offset": 2,
+                    "highlight_end_offset": 29,
+                    "values": []
+                }
+            },
+            "type": "diagnostic"
+        }
+        "#;
+        let step: TerraformPlanStep = serde_json::from_str(json).unwrap();
+        assert_eq!(step.record_type, "diagnostic");
+        let diagnostic = step.diagnostic.unwrap();
+        assert_eq!(diagnostic.severity, "warning");
+        assert_eq!(diagnostic.address, Some("azurerm_key_vault.kv".to_string()));
+        let range = diagnostic.range.unwrap();
+        assert_eq!(range.filename, "main.tf");
+        assert_eq!(range.start.line, 41);
+        assert_eq!(range.end.column, 30);
+    }
+
+    #[test]
+    fn test_deserialize_terraform_resource_drift() {
+        let json = r#"
+        {
+            "@level": "info",
+            "@message": "azurerm_resource_group.rg: Drift detected (update)",
+            "@module": "terraform.ui",
+            "@timestamp": "2024-02-23T13:50:04.118923-06:00",
+            "change": {
+                "resource": {
+                    "addr": "azurerm_resource_group.rg",
+                    "module": "",
+                    "resource": "azurerm_resource_group.rg",
+                    "implied_provider": "azurerm",
+                    "resource_type": "azurerm_resource_group",
+                    "resource_name": "rg",
+                    "resource_key": null
+                },
+                "action": "update"
+            },
+            "type": "resource_drift"
+        }
+        "#;
+        let drift: TerraformPlanStep = serde_json::from_str(json).unwrap();
+        assert_eq!(drift.record_type, "resource_drift");
+        let change = drift.change.unwrap();
+        assert_eq!(change.resource.get_address(), "azurerm_resource_group.rg");
+        assert_eq!(change.resource.get_resource_type(), "azurerm_resource_group");
+        assert_eq!(change.action, "update");
+    }
+
+    #[test]
+    fn test_deserialize_opentofu_version() {
+        let json = r#"
+        {
+            "@level": "info",
+            "@message": "OpenTofu 1.6.2",
+            "@module": "tofu.ui",
+            "@timestamp": "2024-03-11T09:12:44.128791-05:00",
+            "tofu": "1.6.2",
+            "type": "version",
+            "ui": "1.2"
+        }
+        "#;
+        let version: TerraformPlanStep = serde_json::from_str(json).unwrap();
+        assert_eq!(version.record_type, "version");
+        assert_eq!(version.module, "tofu.ui");
+        assert_eq!(version.terraform.unwrap(), "1.6.2");
+    }
+
     #[test]
     fn test_deserialize_terraform_apply_start() {
         let json = r#"
@@ -207,7 +442,7 @@ mod terraform_test {
         assert_eq!(&hook.action, "read");
         assert_eq!(&hook.id_key.unwrap(), "id");
         assert_eq!(&hook.id_value.unwrap(), "Y2xpZW50Q29uZmlncy9jbGllbnRJZD0wNGIwNzc5NS04ZGRiLTQ2MWEtYmJlZS0wMmY5ZTFiZjdiNDY7b2JqZWN0SWQ9YmIwOTk4MDctMGI5ZC00YzYzLTk1YWMtZDg2ZjM");
-        assert_eq!(*&hook.elapsed_seconds.unwrap(), 0);
+        assert_eq!(hook.elapsed_seconds.unwrap(), 0);
     }
 
     #[test]
@@ -253,6 +488,85 @@ mod terraform_test {
         assert_eq!(&changes.action, "create");
     }
 
+    #[test]
+    fn test_comparison_resource_keeps_module_address() {
+        let json = r#"
+        {
+            "addr": "module.network.azurerm_subnet.this[\"app\"]",
+            "module": "module.network",
+            "resource": "azurerm_subnet.this[\"app\"]",
+            "implied_provider": "azurerm",
+            "resource_type": "azurerm_subnet",
+            "resource_name": "this",
+            "resource_key": "app"
+        }
+        "#;
+        let resource: TerraformResource = serde_json::from_str(json).unwrap();
+        assert_eq!(resource.resource_key.as_deref(), Some("app"));
+        let types = ResourceTypeMap::default();
+        let result = resource.get_comparison_resource(None, None, &types).unwrap();
+        assert_eq!(result.resource_type, "subnet");
+        assert_eq!(
+            result.address.as_deref(),
+            Some("module.network.azurerm_subnet.this[\"app\"]")
+        );
+        assert_eq!(result.module.as_deref(), Some("module.network"));
+
+        let json = r#"{"addr": "azurerm_subnet.this[0]", "module": "", "resource": "azurerm_subnet.this[0]",
+            "implied_provider": "azurerm", "resource_type": "azurerm_subnet", "resource_name": "this", "resource_key": 0}"#;
+        let resource: TerraformResource = serde_json::from_str(json).unwrap();
+        assert_eq!(resource.resource_key.as_deref(), Some("0"));
+        assert_eq!(resource.get_comparison_resource(None, None, &types).unwrap().module, None);
+    }
+
+    #[test]
+    fn test_azapi_comparison_resource() {
+        let resource = TerraformResource {
+            addr: "azapi_resource.app".to_string(),
+            resource: "azapi_resource.app".to_string(),
+            implied_provider: "azapi".to_string(),
+            resource_type: "azapi_resource".to_string(),
+            resource_name: "app".to_string(),
+            ..Default::default()
+        };
+        let values = serde_json::json!({"type": "Microsoft.KeyVault/vaults@2023-07-01", "name": "kv-test"});
+        let types = ResourceTypeMap::default();
+        let result = resource.get_comparison_resource(Some(&values), None, &types).unwrap();
+        assert_eq!(result.resource_type, "key_vault");
+        assert_eq!(result.api_version.as_deref(), Some("2023-07-01"));
+
+        let values = serde_json::json!({"type": "Microsoft.App/containerApps@2023-05-01"});
+        let result = resource.get_comparison_resource(Some(&values), None, &types).unwrap();
+        assert_eq!(result.resource_type, "Microsoft.App/containerApps");
+
+        let overrides = HashMap::from([("Microsoft.App/containerApps".to_string(), String::new())]);
+        let types = ResourceTypeMap::new(AzureCloud::Public, &overrides);
+        assert!(resource.get_comparison_resource(Some(&values), None, &types).is_none());
+        assert_eq!(
+            resource.get_comparison_resource(None, None, &types).unwrap().resource_type,
+            "azapi_resource"
+        );
+    }
+
+    #[test]
+    fn test_resource_class() {
+        let resource = |resource: &str, provider: &str| TerraformResource {
+            addr: format!("module.app.{}", resource),
+            module: "module.app".to_string(),
+            resource: resource.to_string(),
+            implied_provider: provider.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(resource("azurerm_key_vault.kv", "azurerm").get_class(), ResourceClass::Azure);
+        assert_eq!(resource("azapi_resource.app", "azapi").get_class(), ResourceClass::Azure);
+        assert_eq!(resource("random_string.suffix", "random").get_class(), ResourceClass::Helper);
+        assert_eq!(resource("time_sleep.wait", "time").get_class(), ResourceClass::Helper);
+        assert_eq!(
+            resource("data.azurerm_client_config.current", "azurerm").get_class(),
+            ResourceClass::Data
+        );
+    }
+
     #[test]
     fn test_deserialize_terraform_change_summary() {
         let json = r#"
@@ -281,10 +595,10 @@ mod terraform_test {
         assert_eq!(change_summary.module, "terraform.ui");
         assert_eq!(change_summary.timestamp, "2024-02-23T13:50:04.652705-06:00");
         let changes = change_summary.changes.unwrap();
-        assert_eq!(*&changes.add, 5);
-        assert_eq!(*&changes.change, 0);
-        assert_eq!(*&changes.import, 0);
-        assert_eq!(*&changes.remove, 0);
+        assert_eq!(changes.add, 5);
+        assert_eq!(changes.change, 0);
+        assert_eq!(changes.import, 0);
+        assert_eq!(changes.remove, 0);
         assert_eq!(&changes.operation, "plan");
     }
 
@@ -408,24 +722,18 @@ mod terraform_test {
         assert_eq!(outputs.module, "terraform.ui");
         assert_eq!(outputs.timestamp, "2024-02-23T13:50:04.652705-06:00");
         let inner_outputs = outputs.outputs.unwrap();
-        assert_eq!(*&inner_outputs.len(), 25);
-        assert_eq!(
-            *&inner_outputs.get("AZURE_AKS_CLUSTER_ID").unwrap().sensitive,
-            false
-        );
+        assert_eq!(inner_outputs.len(), 25);
+        assert!(!inner_outputs.get("AZURE_AKS_CLUSTER_ID").unwrap().sensitive);
         assert_eq!(
-            *&inner_outputs.get("AZURE_AKS_CLUSTER_ID").unwrap().action,
+            inner_outputs.get("AZURE_AKS_CLUSTER_ID").unwrap().action,
             "create"
         );
+        assert!(!inner_outputs
+            .get("AZURE_AKS_CLUSTER_NAME")
+            .unwrap()
+            .sensitive);
         assert_eq!(
-            *&inner_outputs
-                .get("AZURE_AKS_CLUSTER_NAME")
-                .unwrap()
-                .sensitive,
-            false
-        );
-        assert_eq!(
-            *&inner_outputs.get("AZURE_AKS_CLUSTER_NAME").unwrap().action,
+            inner_outputs.get("AZURE_AKS_CLUSTER_NAME").unwrap().action,
             "create"
         );
     }
@@ -0,0 +1,123 @@
+use super::{MalformedLine, TerraformPlanStep, TerraformResourceChange};
+use log::{debug, warn};
+
+/// The longest malformed line kept for the report.
+const MAX_MALFORMED_LENGTH: usize = 500;
+
+/// Builds a `TerraformResourceChange` from the `-json` event stream one line
+/// at a time, so events can be read while `terraform` is still running.
+/// Lines that are not events are recorded rather than treated as fatal.
+#[derive(Debug, Default)]
+pub struct TerraformPlanParser {
+    result: TerraformResourceChange,
+    line_number: usize,
+}
+
+impl TerraformPlanParser {
+    pub fn new() -> Self {
+        TerraformPlanParser::default()
+    }
+
+    /// Parses a complete event stream, such as one saved by an earlier run.
+    pub fn parse(output: &str) -> TerraformResourceChange {
+        let mut parser = TerraformPlanParser::new();
+        for line in output.lines() {
+            parser.push_line(line);
+        }
+        parser.finish()
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        self.line_number += 1;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() {
+            return;
+        }
+        debug!("Terraform Plan output: {:?}", line);
+        match serde_json::from_str::<TerraformPlanStep>(line) {
+            Ok(step) => self.add_step(step),
+            Err(e) => {
+                warn!(
+                    "Skipping Terraform Plan output line {}: {}",
+                    self.line_number, e
+                );
+                self.result.malformed_lines.push(MalformedLine {
+                    line_number: self.line_number,
+                    content: line.chars().take(MAX_MALFORMED_LENGTH).collect(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    pub fn finish(self) -> TerraformResourceChange {
+        self.result
+    }
+
+    fn add_step(&mut self, step: TerraformPlanStep) {
+        let result = &mut self.result;
+        match step.record_type.as_str() {
+            "version" => result.version = Some(step),
+            "apply_start" => result.apply_start.push(step),
+            "apply_complete" => result.apply_complete.push(step),
+            "apply_errored" => result.apply_errored.push(step),
+            "refresh_complete" => result.refresh_complete.push(step),
+            "planned_change" => result.planned_change.push(step),
+            "resource_drift" => result.resource_drift.push(step),
+            "change_summary" => result.change_summary = Some(step),
+            "outputs" => result.outputs = Some(step),
+            "diagnostic" => result.diagnostics.push(step),
+            "log" => {
+                debug!("Terraform log: {}", step.message);
+                result.other.push(step);
+            }
+            "refresh_start"
+            | "apply_progress"
+            | "provision_start"
+            | "provision_progress"
+            | "provision_complete"
+            | "provision_errored"
+            | "ephemeral_op_start"
+            | "ephemeral_op_progress"
+            | "ephemeral_op_complete"
+            | "ephemeral_op_errored" => {
+                result.other.push(step);
+            }
+            record_type if record_type.starts_with("test_") => result.other.push(step),
+            record_type => {
+                debug!(
+                    "Keeping Terraform Plan output of unknown type {}",
+                    record_type
+                );
+                result.other.push(step);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod terraform_parser_test {
+    use super::*;
+
+    #[test]
+    fn test_parser_tolerates_malformed_lines() {
+        let output = concat!(
+            "{\"@level\":\"info\",\"@message\":\"Terraform 1.6.5\",\"@module\":\"terraform.ui\",\"@timestamp\":\"2024-02-23T13:49:28.479064-06:00\",\"terraform\":\"1.6.5\",\"type\":\"version\",\"ui\":\"1.2\"}\r\n",
+            "Using wrapper script v2\r\n",
+            "\r\n",
+            "{\"type\":\"planned_change\",\"change\":{\"resource\":{\"addr\":\"azurerm_subnet.this[0]\",\"module\":\"\",\"resource\":\"azurerm_subnet.this[0]\",\"implied_provider\":\"azurerm\",\"resource_type\":\"azurerm_subnet\",\"resource_name\":\"this\",\"resource_key\":0},\"action\":\"create\"}}\r\n",
+            "{\"type\":\"refresh_start\",\"hook\":{\"resource\":{\"addr\":\"data.azurerm_client_config.current\",\"module\":\"\",\"resource\":\"data.azurerm_client_config.current\",\"implied_provider\":\"azurerm\",\"resource_type\":\"azurerm_client_config\",\"resource_name\":\"current\",\"resource_key\":null}}}\n",
+            "{\"type\":\"change_summary\",\"changes\":{\"add\":1,\"change\":0,\"remove\":0,\"operation\":\"plan\"}}\n",
+            "panic: runtime error: invalid memory address",
+        );
+        let result = TerraformPlanParser::parse(output);
+        assert_eq!(result.version.unwrap().terraform.unwrap(), "1.6.5");
+        assert_eq!(result.planned_change.len(), 1);
+        assert_eq!(result.other.len(), 1);
+        assert!(result.change_summary.is_some());
+        assert_eq!(result.malformed_lines.len(), 2);
+        assert_eq!(result.malformed_lines[0].line_number, 2);
+        assert_eq!(result.malformed_lines[0].content, "Using wrapper script v2");
+        assert_eq!(result.malformed_lines[1].line_number, 7);
+    }
+}